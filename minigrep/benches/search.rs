@@ -0,0 +1,86 @@
+//! Times literal search over a large generated text, against searching it
+//! a line at a time as `search` used to.
+//!
+//! Run with `cargo bench`.
+
+extern crate minigrep;
+
+use std::io;
+use std::str;
+use std::time::{Duration, Instant};
+
+use minigrep::matcher::Literal;
+use minigrep::stream::Searcher;
+
+const ROUNDS: u32 = 10;
+
+/// About 40 MB of prose-like lines, with the query on one in a thousand.
+fn text() -> String {
+    let words = ["the", "quick", "brown", "fox", "jumps", "over", "lazy", "dog",
+                 "and", "then", "some", "more", "words", "follow", "after"];
+    let mut text = String::new();
+    let mut seed: u32 = 1;
+    for line in 0..600_000 {
+        for _ in 0..12 {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            text.push_str(words[(seed >> 16) as usize % words.len()]);
+            text.push(' ');
+        }
+        if line % 1000 == 0 {
+            text.push_str("needle");
+        }
+        text.push('\n');
+    }
+    text
+}
+
+/// Lines containing `query`, found as `search` once did: every line is
+/// split out, checked to be UTF-8 as matchers take bytes, and searched.
+fn line_at_a_time<'a>(query: &str, contents: &'a str) -> Vec<(usize, &'a str)> {
+    contents.lines().enumerate()
+        .filter(|&(_, line)| {
+            str::from_utf8(line.as_bytes()).is_ok_and(|l| l.contains(query))
+        })
+        .map(|(i, line)| (i + 1, line))
+        .collect()
+}
+
+/// The best of `ROUNDS` runs of `f`, and what it found.
+fn time<F: FnMut() -> usize>(mut f: F) -> (Duration, usize) {
+    let mut best = Duration::MAX;
+    let mut found = 0;
+    for _ in 0..ROUNDS {
+        let start = Instant::now();
+        found = f();
+        best = best.min(start.elapsed());
+    }
+    (best, found)
+}
+
+fn report(name: &str, (elapsed, found): (Duration, usize), baseline: Duration) {
+    println!("{:<24} {:>8.2} ms {:>6} lines {:>6.1}x", name,
+             elapsed.as_secs_f64() * 1000.0, found,
+             baseline.as_secs_f64() / elapsed.as_secs_f64());
+}
+
+fn main() {
+    let text = text();
+    let query = "needle";
+    println!("searching {} MB for {:?}", text.len() / 1_000_000, query);
+
+    let old = time(|| line_at_a_time(query, &text).len());
+    let baseline = old.0;
+    report("line at a time", old, baseline);
+
+    report("search", time(|| minigrep::search(query, &text).len()), baseline);
+
+    let literal = Literal::new(query);
+    let searcher = Searcher::new(&literal);
+    report("Searcher::search", time(|| {
+        let reader = io::BufReader::with_capacity(64 * 1024, text.as_bytes());
+        searcher.search(reader, |_| Ok(())).unwrap()
+    }), baseline);
+    report("Searcher::search_slice", time(|| {
+        searcher.search_slice(text.as_bytes(), |_| Ok(())).unwrap()
+    }), baseline);
+}
@@ -0,0 +1,299 @@
+//! Searching for many literal patterns at once.
+//!
+//! The patterns are built into an Aho-Corasick automaton: a trie of the
+//! patterns where each state also knows the longest proper suffix of its
+//! path that is in the trie.  Text is read once, a character at a time,
+//! however many patterns there are.
+//!
+//! Case-insensitive automatons are built from, and fed, case foldings, as
+//! `fold` describes.  Matches still cover whole characters of the text.
+
+use std::collections::VecDeque;
+
+use fold;
+
+/// One pattern found in the text.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PatternMatch {
+    /// Index of the pattern in the list given to `AhoCorasick::new`.
+    pub pattern: usize,
+    /// Byte range of the match in the text.
+    pub start: usize,
+    pub end: usize,
+}
+
+struct State {
+    /// Transitions, sorted by character.
+    next: Vec<(char, usize)>,
+    /// State for the longest proper suffix of this state's path.
+    fail: usize,
+    /// Patterns ending here, including those ending at suffix states.
+    out: Vec<usize>,
+    /// Length of this state's path.
+    depth: usize,
+}
+
+impl State {
+    fn new(depth: usize) -> State {
+        State { next: Vec::new(), fail: 0, out: Vec::new(), depth }
+    }
+
+    fn goto(&self, c: char) -> Option<usize> {
+        self.next.binary_search_by_key(&c, |t| t.0).ok().map(|i| self.next[i].1)
+    }
+}
+
+/// An automaton matching any of a list of literal patterns.
+pub struct AhoCorasick {
+    states: Vec<State>,
+    /// Length of each pattern, in characters as fed to the automaton.
+    lens: Vec<usize>,
+    /// The first empty pattern, which matches anywhere.
+    empty: Option<usize>,
+    case_insensitive: bool,
+}
+
+impl AhoCorasick {
+    /// Build an automaton for `patterns`.
+    pub fn new<S: AsRef<str>>(patterns: &[S], case_insensitive: bool)
+        -> AhoCorasick {
+        let mut ac = AhoCorasick { states: vec![State::new(0)], lens: Vec::new(),
+                                   empty: None, case_insensitive };
+        for (i, pattern) in patterns.iter().enumerate() {
+            let chars = if case_insensitive {
+                fold::fold_str(pattern.as_ref())
+            } else {
+                pattern.as_ref().chars().collect()
+            };
+            ac.lens.push(chars.len());
+            if chars.is_empty() {
+                ac.empty = ac.empty.or(Some(i));
+                continue;
+            }
+            let mut s = 0;
+            for c in chars {
+                s = match ac.states[s].goto(c) {
+                    Some(t) => t,
+                    None => {
+                        let t = ac.states.len();
+                        let depth = ac.states[s].depth + 1;
+                        ac.states.push(State::new(depth));
+                        let next = &mut ac.states[s].next;
+                        let at = next.binary_search_by_key(&c, |t| t.0)
+                            .unwrap_err();
+                        next.insert(at, (c, t));
+                        t
+                    },
+                };
+            }
+            ac.states[s].out.push(i);
+        }
+        ac.link();
+        ac
+    }
+
+    /// Set the failure links, breadth first so that shorter paths are done
+    /// before the longer ones that depend on them.
+    fn link(&mut self) {
+        let mut queue: VecDeque<usize> = self.states[0].next.iter()
+            .map(|t| t.1)
+            .collect();
+        while let Some(s) = queue.pop_front() {
+            for i in 0..self.states[s].next.len() {
+                let (c, t) = self.states[s].next[i];
+                let mut f = self.states[s].fail;
+                let fail = loop {
+                    match self.states[f].goto(c) {
+                        Some(g) if g != t => break g,
+                        _ if f == 0 => break 0,
+                        _ => f = self.states[f].fail,
+                    }
+                };
+                self.states[t].fail = fail;
+                let inherited = self.states[fail].out.clone();
+                self.states[t].out.extend(inherited);
+                queue.push_back(t);
+            }
+        }
+    }
+
+    fn next(&self, mut s: usize, c: char) -> usize {
+        loop {
+            if let Some(t) = self.states[s].goto(c) {
+                return t;
+            }
+            if s == 0 {
+                return 0;
+            }
+            s = self.states[s].fail;
+        }
+    }
+
+    /// Byte offset in `text` where a match of `len` characters ending at
+    /// byte `end` starts, or `None` if it would start inside a character.
+    fn start(&self, text: &str, end: usize, len: usize) -> Option<usize> {
+        let mut left = len;
+        for (i, c) in text[..end].char_indices().rev() {
+            let n = if self.case_insensitive { fold::fold(c).count() } else { 1 };
+            if n > left {
+                return None;
+            }
+            left -= n;
+            if left == 0 {
+                return Some(i);
+            }
+        }
+        None
+    }
+
+    /// Find the first match in `text` at or after byte `at`, the one
+    /// `find_all` would find first in `text[at..]`, reading only as far as
+    /// a match starting no later than it could end.
+    pub fn find_at(&self, text: &str, at: usize) -> Option<PatternMatch> {
+        let text = &text[at..];
+        // The best match so far, and where it starts in characters fed.
+        let mut best: Option<(PatternMatch, usize)> = None;
+        let mut s = 0;
+        let mut fed = 0;
+        for (i, c) in text.char_indices() {
+            if self.case_insensitive {
+                for f in fold::fold(c) {
+                    s = self.next(s, f);
+                    fed += 1;
+                }
+            } else {
+                s = self.next(s, c);
+                fed += 1;
+            }
+            let end = i + c.len_utf8();
+            for &pattern in &self.states[s].out {
+                let start = match self.start(text, end, self.lens[pattern]) {
+                    Some(start) => start,
+                    None => continue,
+                };
+                // Matches end later and later, so only an earlier start, or
+                // a lower pattern ending here too, beats the best.
+                let better = best.is_none_or(|(b, _)| {
+                    start < b.start || (start == b.start
+                                        && (end > b.end || pattern < b.pattern))
+                });
+                if better {
+                    best = Some((PatternMatch { pattern, start, end },
+                                 fed - self.lens[pattern]));
+                }
+            }
+            // Any match still to come starts where the current state's path
+            // does, or later.
+            if best.is_some_and(|(_, first)| fed - self.states[s].depth > first) {
+                break;
+            }
+        }
+        match best {
+            Some((m, _)) => Some(PatternMatch { start: at + m.start, end: at + m.end,
+                                                ..m }),
+            None => self.empty.map(|pattern| PatternMatch { pattern, start: at,
+                                                             end: at }),
+        }
+    }
+
+    /// Find the matches in `text`, leftmost first, preferring the longest
+    /// match at each position, and not overlapping.
+    ///
+    /// An empty pattern matches at the start of text where nothing else
+    /// does.
+    pub fn find_all(&self, text: &str) -> Vec<PatternMatch> {
+        let mut found = Vec::new();
+        let mut s = 0;
+        for (i, c) in text.char_indices() {
+            if self.case_insensitive {
+                for f in fold::fold(c) {
+                    s = self.next(s, f);
+                }
+            } else {
+                s = self.next(s, c);
+            }
+            // Only matches ending with a whole character count.
+            let end = i + c.len_utf8();
+            for &pattern in &self.states[s].out {
+                if let Some(start) = self.start(text, end, self.lens[pattern]) {
+                    found.push(PatternMatch { pattern, start, end });
+                }
+            }
+        }
+
+        found.sort_by(|a, b| {
+            (a.start, b.end, a.pattern).cmp(&(b.start, a.end, b.pattern))
+        });
+        let mut last_end = 0;
+        found.retain(|m| {
+            let keep = m.start >= last_end;
+            if keep {
+                last_end = m.end;
+            }
+            keep
+        });
+        if let (true, Some(pattern)) = (found.is_empty(), self.empty) {
+            found.push(PatternMatch { pattern, start: 0, end: 0 });
+        }
+        found
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn spans(ac: &AhoCorasick, text: &str) -> Vec<(usize, usize, usize)> {
+        ac.find_all(text).into_iter()
+            .map(|m| (m.pattern, m.start, m.end))
+            .collect()
+    }
+
+    #[test]
+    fn finds_every_pattern() {
+        let ac = AhoCorasick::new(&["he", "she", "his", "hers"], false);
+        assert_eq!(vec![(1, 1, 4), (2, 8, 11)], spans(&ac, "ushers this"));
+        assert_eq!(vec![(0, 0, 2)], spans(&ac, "hex"));
+        assert!(spans(&ac, "HE").is_empty());
+    }
+
+    #[test]
+    fn longest_at_each_position() {
+        let ac = AhoCorasick::new(&["ab", "abcd", "bc", "d"], false);
+        assert_eq!(vec![(1, 0, 4), (3, 5, 6)], spans(&ac, "abcd d"));
+        assert_eq!(vec![(0, 0, 2)], spans(&ac, "abc"));
+    }
+
+    #[test]
+    fn case_insensitive() {
+        let ac = AhoCorasick::new(&["strasse", "ss"], true);
+        assert_eq!(vec![(0, 0, 7)], spans(&ac, "STRAßE"));
+        // Half of a 'ß' is not a match.
+        let ac = AhoCorasick::new(&["s"], true);
+        assert!(spans(&ac, "ß").is_empty());
+    }
+
+    #[test]
+    fn first_match_from_a_position() {
+        let ac = AhoCorasick::new(&["ab", "abcd", "bc", "d", "cd", "x"], false);
+        let text = "xabcd d bcd";
+        for at in 0..text.len() + 1 {
+            let first = ac.find_all(&text[at..]).first()
+                .map(|m| (m.pattern, at + m.start, at + m.end));
+            assert_eq!(first, ac.find_at(text, at).map(|m| (m.pattern, m.start, m.end)));
+        }
+        let ac = AhoCorasick::new(&["strasse", "ss", "a"], true);
+        assert_eq!(Some((0, 1, 8)), ac.find_at("xSTRAßE", 1)
+                   .map(|m| (m.pattern, m.start, m.end)));
+        let ac = AhoCorasick::new(&["x", ""], false);
+        assert_eq!(Some((1, 2, 2)), ac.find_at("abc", 2)
+                   .map(|m| (m.pattern, m.start, m.end)));
+    }
+
+    #[test]
+    fn empty_pattern() {
+        let ac = AhoCorasick::new(&["x", ""], false);
+        assert_eq!(vec![(1, 0, 0)], spans(&ac, "abc"));
+        assert_eq!(vec![(0, 1, 2)], spans(&ac, "axc"));
+    }
+}
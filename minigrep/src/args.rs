@@ -0,0 +1,569 @@
+//! Command-line option parsing.
+//!
+//! Options are described once, in `OPTIONS`, and that table drives both the
+//! parser and the usage text.  Short flags can be combined (`-in`), values
+//! can be attached or separate (`-A2`, `-A 2`, `--context=2`,
+//! `--context 2`), and `--` ends the options.
+
+use std::env;
+
+use walk::TYPES;
+use {Config, Error};
+
+/// One command-line option.
+pub struct Opt {
+    pub short: Option<char>,
+    pub long: &'static str,
+    /// Name of the option's value, or `None` for a flag.
+    pub value: Option<&'static str>,
+    pub help: &'static str,
+}
+
+pub static OPTIONS: &[Opt] = &[
+    Opt { short: Some('e'), long: "regexp", value: Some("PATTERN"),
+          help: "Search for PATTERN; can be given more than once" },
+    Opt { short: Some('f'), long: "file", value: Some("FILE"),
+          help: "Search for each line of FILE" },
+    Opt { short: Some('i'), long: "ignore-case", value: None,
+          help: "Search without regard to case" },
+    Opt { short: Some('s'), long: "case-sensitive", value: None,
+          help: "Search with regard to case (default)" },
+    Opt { short: Some('S'), long: "smart-case", value: None,
+          help: "Ignore case unless the query has an uppercase letter" },
+    Opt { short: Some('E'), long: "regex", value: None,
+          help: "Treat the query as a regular expression" },
+    Opt { short: Some('F'), long: "fixed-strings", value: None,
+          help: "Treat the query as a plain string (default)" },
+    Opt { short: None, long: "fuzzy", value: Some("K"),
+          help: "Treat the query as a plain string found within K edits" },
+    Opt { short: Some('w'), long: "word-regexp", value: None,
+          help: "Match only whole words" },
+    Opt { short: Some('x'), long: "line-regexp", value: None,
+          help: "Match only whole lines" },
+    Opt { short: None, long: "boundary", value: Some("CHARS"),
+          help: "Match only between any of CHARS or the ends of lines" },
+    Opt { short: Some('U'), long: "multiline", value: None,
+          help: "Let matches span lines, showing every line they touch" },
+    Opt { short: Some('v'), long: "invert-match", value: None,
+          help: "Select the lines that do not match" },
+    Opt { short: Some('c'), long: "count", value: None,
+          help: "Show only the number of matching lines in each file" },
+    Opt { short: Some('l'), long: "files-with-matches", value: None,
+          help: "Show only the names of files with matches" },
+    Opt { short: None, long: "files-without-match", value: None,
+          help: "Show only the names of files without matches" },
+    Opt { short: None, long: "json", value: None,
+          help: "Show results as JSON Lines, one event per line" },
+    Opt { short: Some('q'), long: "quiet", value: None,
+          help: "Show nothing; exit with status 0 if anything matched" },
+    Opt { short: Some('m'), long: "max-count", value: Some("NUM"),
+          help: "Stop reading a file after NUM matching lines" },
+    Opt { short: None, long: "max-total", value: Some("NUM"),
+          help: "Stop the whole search after NUM matching lines" },
+    Opt { short: Some('o'), long: "only-matching", value: None,
+          help: "Show each match on a line of its own, not the whole line" },
+    Opt { short: None, long: "format", value: Some("TEMPLATE"),
+          help: "Show TEMPLATE for each match, where $1 is a group" },
+    Opt { short: Some('r'), long: "replace", value: Some("TEXT"),
+          help: "Show a diff replacing each match with TEXT, where $1 is a group" },
+    Opt { short: None, long: "in-place", value: None,
+          help: "With --replace, write the changes to the files" },
+    Opt { short: None, long: "binary-files", value: Some("TYPE"),
+          help: "Treat binary files as binary (default), text or without-match" },
+    Opt { short: Some('a'), long: "text", value: None,
+          help: "Search binary files as text" },
+    Opt { short: Some('I'), long: "skip-binary", value: None,
+          help: "Skip binary files" },
+    Opt { short: Some('n'), long: "line-number", value: None,
+          help: "Show the line number of each result" },
+    Opt { short: None, long: "column", value: None,
+          help: "Show the column of the first match on each line" },
+    Opt { short: Some('b'), long: "byte-offset", value: None,
+          help: "Show the byte offset of each line" },
+    Opt { short: Some('A'), long: "after-context", value: Some("NUM"),
+          help: "Show NUM lines after each match" },
+    Opt { short: Some('B'), long: "before-context", value: Some("NUM"),
+          help: "Show NUM lines before each match" },
+    Opt { short: Some('C'), long: "context", value: Some("NUM"),
+          help: "Show NUM lines before and after each match" },
+    Opt { short: Some('L'), long: "follow", value: None,
+          help: "Follow symbolic links inside directories" },
+    Opt { short: None, long: "max-depth", value: Some("NUM"),
+          help: "Descend at most NUM directories below each PATH" },
+    Opt { short: None, long: "max-filesize", value: Some("SIZE"),
+          help: "Skip files bigger than SIZE bytes; K, M or G may follow" },
+    Opt { short: None, long: "no-mmap", value: None,
+          help: "Read big files instead of mapping them into memory" },
+    Opt { short: Some('j'), long: "threads", value: Some("NUM"),
+          help: "Search NUM files at once (default: one per CPU)" },
+    Opt { short: None, long: "sort", value: Some("HOW"),
+          help: "Print files in path order with 'path', or as searched with 'none'" },
+    Opt { short: None, long: "color", value: Some("WHEN"),
+          help: "Highlight matches: auto (default), always or never" },
+    Opt { short: None, long: "hidden", value: None,
+          help: "Search hidden files and directories" },
+    Opt { short: None, long: "no-ignore", value: None,
+          help: "Search what .gitignore and .ignore files leave out" },
+    Opt { short: Some('g'), long: "glob", value: Some("GLOB"),
+          help: "Search only files matching GLOB, or not matching !GLOB" },
+    Opt { short: None, long: "exclude", value: Some("GLOB"),
+          help: "Skip files and directories matching GLOB" },
+    Opt { short: Some('t'), long: "type", value: Some("TYPE"),
+          help: "Search only files of TYPE, such as rust" },
+    Opt { short: Some('T'), long: "type-not", value: Some("TYPE"),
+          help: "Skip files of TYPE" },
+    Opt { short: None, long: "verbose", value: None,
+          help: "Describe the search on stderr before starting" },
+    Opt { short: None, long: "help", value: None,
+          help: "Show this help and exit" },
+    Opt { short: Some('V'), long: "version", value: None,
+          help: "Show the version and exit" },
+];
+
+/// Environment variables that supply defaults for options.  All but the
+/// original `CASE_INSENSITIVE` are prefixed, so as not to be taken from
+/// variables set for other programs.
+static ENVIRONMENT: &[(&str, &str)] = &[
+    ("CASE_INSENSITIVE", "--ignore-case"),
+    ("MINIGREP_REGEX", "--regex"),
+    ("MINIGREP_LINE_NUMBER", "--line-number"),
+    ("MINIGREP_COLUMN", "--column"),
+    ("MINIGREP_BYTE_OFFSET", "--byte-offset"),
+    ("MINIGREP_AFTER_CONTEXT", "--after-context"),
+    ("MINIGREP_BEFORE_CONTEXT", "--before-context"),
+    ("MINIGREP_CONTEXT", "--context"),
+    ("MINIGREP_FOLLOW_LINKS", "--follow"),
+    ("MINIGREP_MAX_DEPTH", "--max-depth"),
+];
+
+/// What the command line asks for.
+pub enum Command {
+    Search(Box<Config>),
+    Help,
+    Version,
+}
+
+/// Options found on a command line, in order, and the other arguments.
+#[derive(Debug, PartialEq)]
+pub struct Matches {
+    opts: Vec<(&'static str, Option<String>)>,
+    pub free: Vec<String>,
+}
+
+impl Matches {
+    /// Check if the option called `long` was given.
+    pub fn flag(&self, long: &str) -> bool {
+        self.opts.iter().any(|o| o.0 == long)
+    }
+
+    /// The last value given to the option called `long`.
+    pub fn value(&self, long: &str) -> Option<&str> {
+        self.opts.iter().rev()
+            .find(|o| o.0 == long)
+            .and_then(|o| o.1.as_deref())
+    }
+
+    /// Every value given to the option called `long`, in order.
+    pub fn values(&self, long: &str) -> Vec<&str> {
+        self.opts.iter()
+            .filter(|o| o.0 == long)
+            .filter_map(|o| o.1.as_deref())
+            .collect()
+    }
+
+    /// The last value given to `long`, as a number.
+    pub fn number(&self, long: &str) -> Result<Option<usize>, Error> {
+        match self.value(long) {
+            Some(value) => value.parse().map(Some).map_err(|_| {
+                Error::Argument(format!(
+                    "invalid value '{}' for '--{}': expected a number",
+                    value, long))
+            }),
+            None => Ok(None),
+        }
+    }
+
+    /// The last value given to `long`, as a number of bytes, which may end
+    /// in `K`, `M` or `G` for kibibytes, mebibytes or gibibytes.
+    pub fn size(&self, long: &str) -> Result<Option<u64>, Error> {
+        let value = match self.value(long) {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        let (digits, shift) = [("K", 10), ("M", 20), ("G", 30)].iter()
+            .find_map(|&(unit, shift)| value.strip_suffix(unit).map(|d| (d, shift)))
+            .unwrap_or((value, 0));
+        digits.parse::<u64>().ok()
+            .and_then(|n| n.checked_mul(1 << shift))
+            .map(Some)
+            .ok_or_else(|| Error::Argument(format!(
+                "invalid value '{}' for '--{}': expected a size such as 100K",
+                value, long)))
+    }
+
+    /// Which of the options in `longs` was given last.
+    pub fn last_of(&self, longs: &[&str]) -> Option<&'static str> {
+        self.opts.iter().rev()
+            .find(|o| longs.contains(&o.0))
+            .map(|o| o.0)
+    }
+}
+
+/// Parse a command line, the first argument being the program name, with
+/// defaults from the environment.
+pub fn parse<I>(args: I) -> Result<Command, Error>
+    where I: IntoIterator<Item = String>
+{
+    parse_with_env(args, |var| env::var(var).ok())
+}
+
+/// Parse a command line as `parse` does, looking up the environment
+/// variables that set defaults with `var`.
+pub fn parse_with_env<I, F>(args: I, var: F) -> Result<Command, Error>
+    where I: IntoIterator<Item = String>, F: Fn(&str) -> Option<String>
+{
+    let mut matches = parse_options(args.into_iter().skip(1))?;
+    matches.opts.splice(0..0, defaults(var));
+    if matches.flag("help") {
+        return Ok(Command::Help);
+    }
+    if matches.flag("version") {
+        return Ok(Command::Version);
+    }
+    Config::new(&matches).map(|config| Command::Search(Box::new(config)))
+}
+
+/// Options set by the environment variables `lookup` finds, to come before
+/// any given options.
+fn defaults<F>(lookup: F) -> Vec<(&'static str, Option<String>)>
+    where F: Fn(&str) -> Option<String>
+{
+    let mut opts = Vec::new();
+    for &(var, option) in ENVIRONMENT {
+        if let Some(value) = lookup(var) {
+            // CASE_INSENSITIVE=smart asks for smart case instead.
+            let option = if var == "CASE_INSENSITIVE" && value == "smart" {
+                "--smart-case"
+            } else {
+                option
+            };
+            let opt = OPTIONS.iter().find(|o| o.long == &option[2..]).unwrap();
+            opts.push((opt.long, opt.value.map(|_| value)));
+        }
+    }
+    opts
+}
+
+/// Split arguments into options and other arguments.
+pub fn parse_options<I>(args: I) -> Result<Matches, Error>
+    where I: Iterator<Item = String>
+{
+    split(args).map_err(Error::Argument)
+}
+
+fn split<I>(mut args: I) -> Result<Matches, String>
+    where I: Iterator<Item = String>
+{
+    let mut matches = Matches { opts: Vec::new(), free: Vec::new() };
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            matches.free.extend(args);
+            break;
+        } else if let Some(long) = arg.strip_prefix("--") {
+            let (name, attached) = match long.find('=') {
+                Some(i) => (&long[..i], Some(long[i + 1..].to_string())),
+                None => (long, None),
+            };
+            let opt = match OPTIONS.iter().find(|o| o.long == name) {
+                Some(opt) => opt,
+                None => return Err(format!("unknown option '--{}'", name)),
+            };
+            let value = match (opt.value, attached) {
+                (None, Some(_)) => return Err(format!(
+                    "option '--{}' does not take a value", name)),
+                (None, None) => None,
+                (Some(_), Some(value)) => Some(value),
+                (Some(_), None) => match args.next() {
+                    Some(value) => Some(value),
+                    None => return Err(format!(
+                        "option '--{}' requires a value", name)),
+                },
+            };
+            matches.opts.push((opt.long, value));
+        } else if arg.starts_with('-') && arg.len() > 1 {
+            // A run of short flags; one taking a value ends the run.
+            for (i, c) in arg.char_indices().skip(1) {
+                let opt = match OPTIONS.iter().find(|o| o.short == Some(c)) {
+                    Some(opt) => opt,
+                    None => return Err(format!("unknown option '-{}'", c)),
+                };
+                if opt.value.is_none() {
+                    matches.opts.push((opt.long, None));
+                    continue;
+                }
+                let rest = &arg[i + c.len_utf8()..];
+                let value = if !rest.is_empty() {
+                    rest.to_string()
+                } else {
+                    match args.next() {
+                        Some(value) => value,
+                        None => return Err(format!(
+                            "option '-{}' requires a value", c)),
+                    }
+                };
+                matches.opts.push((opt.long, Some(value)));
+                break;
+            }
+        } else {
+            matches.free.push(arg);
+        }
+    }
+    Ok(matches)
+}
+
+/// Usage text, listing every option in `OPTIONS`.
+pub fn usage() -> String {
+    let mut text = String::from("\
+Usage: minigrep [OPTIONS] QUERY [PATH...]
+       minigrep [OPTIONS] -e PATTERN... [-f FILE...] [PATH...]
+
+Search for QUERY in each PATH, descending into directories.  With no PATH,
+or when PATH is -, read standard input.  A line matches if any pattern
+matches it; a QUERY with several lines is a pattern for each.  The exit
+status is 0 if a line matched, 1 if none did, and 2 if there was an error.
+
+Options:
+");
+    let names: Vec<String> = OPTIONS.iter()
+        .map(|o| {
+            let short = match o.short {
+                Some(c) => format!("-{}, ", c),
+                None => String::from("    "),
+            };
+            match o.value {
+                Some(value) => format!("{}--{} {}", short, o.long, value),
+                None => format!("{}--{}", short, o.long),
+            }
+        })
+        .collect();
+    let width = names.iter().map(|n| n.len()).max().unwrap_or(0);
+    for (name, opt) in names.iter().zip(OPTIONS) {
+        text.push_str(&format!("  {:width$}  {}\n", name, opt.help,
+                               width = width));
+    }
+
+    text.push_str("\nThese environment variables set defaults:\n");
+    for &(var, option) in ENVIRONMENT {
+        text.push_str(&format!("  {:25}{}\n", var, option));
+    }
+    text.push_str("Set CASE_INSENSITIVE=smart for --smart-case.\n");
+
+    let types: Vec<&str> = TYPES.iter().map(|t| t.0).collect();
+    text.push_str(&format!("\nFile types: {}\n", types.join(", ")));
+    text
+}
+
+/// Version text.
+pub fn version() -> String {
+    format!("minigrep {}", env!("CARGO_PKG_VERSION"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn options(args: &[&str]) -> Result<Matches, Error> {
+        parse_options(args.iter().map(|a| a.to_string()))
+    }
+
+    fn error(args: &[&str]) -> String {
+        options(args).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn flags_and_free_arguments() {
+        let m = options(&["-in", "query", "--column", "file"]).unwrap();
+        assert!(m.flag("ignore-case"));
+        assert!(m.flag("line-number"));
+        assert!(m.flag("column"));
+        assert!(!m.flag("regex"));
+        assert_eq!(vec!["query", "file"], m.free);
+    }
+
+    #[test]
+    fn values() {
+        let m = options(&["-nA2", "-B", "3", "--context=4",
+                          "--max-depth", "5"]).unwrap();
+        assert_eq!(Some(2), m.number("after-context").unwrap());
+        assert_eq!(Some(3), m.number("before-context").unwrap());
+        assert_eq!(Some("4"), m.value("context"));
+        assert_eq!(Some(5), m.number("max-depth").unwrap());
+        assert!(m.flag("line-number"));
+    }
+
+    #[test]
+    fn double_dash_ends_options() {
+        let m = options(&["-i", "--", "-n", "-"]).unwrap();
+        assert!(!m.flag("line-number"));
+        assert_eq!(vec!["-n", "-"], m.free);
+    }
+
+    #[test]
+    fn last_option_wins() {
+        let m = options(&["-i", "-s"]).unwrap();
+        assert_eq!(Some("case-sensitive"),
+                   m.last_of(&["ignore-case", "case-sensitive"]));
+    }
+
+    #[test]
+    fn errors_name_the_option() {
+        assert_eq!("unknown option '--nope'", error(&["--nope"]));
+        assert_eq!("unknown option '-z'", error(&["-iz"]));
+        assert_eq!("option '-A' requires a value", error(&["-A"]));
+        assert_eq!("option '--column' does not take a value",
+                   error(&["--column=3"]));
+        assert_eq!("invalid value 'x' for '--context': expected a number",
+                   options(&["-Cx"]).unwrap().number("context")
+                       .unwrap_err().to_string());
+    }
+
+    #[test]
+    fn sizes() {
+        let size = |arg: &str| options(&[arg]).unwrap().size("max-filesize");
+        assert_eq!(Some(100), size("--max-filesize=100").unwrap());
+        assert_eq!(Some(2 << 20), size("--max-filesize=2M").unwrap());
+        assert_eq!(Some(1 << 30), size("--max-filesize=1G").unwrap());
+        assert!(size("--max-filesize=1T").is_err());
+        assert!(size("--max-filesize=K").is_err());
+        assert_eq!(None, options(&[]).unwrap().size("max-filesize").unwrap());
+    }
+
+    /// Parse `args` as if no environment variables were set.
+    fn command(args: &[&str]) -> Result<Command, Error> {
+        parse_with_env(args.iter().map(|a| a.to_string()), |_| None)
+    }
+
+    #[test]
+    fn builds_config() {
+        let config = match command(&["minigrep", "-iE", "-C1", "ru.t", "a", "b"]) {
+            Ok(Command::Search(config)) => config,
+            _ => panic!("expected a search"),
+        };
+        assert_eq!("ru.t", config.query);
+        assert_eq!(vec!["a", "b"], config.paths);
+        assert_eq!(::Case::Insensitive, config.case);
+        assert!(!config.case_sensitive);
+        assert_eq!(::Mode::Regex, config.mode);
+        assert_eq!((1, 1), (config.before_context, config.after_context));
+    }
+
+    #[test]
+    fn environment_defaults() {
+        let config = |args: &[&str]| {
+            let env = |var: &str| match var {
+                "CASE_INSENSITIVE" => Some("smart".to_string()),
+                "MINIGREP_CONTEXT" => Some("2".to_string()),
+                "REGEX" | "CONTEXT" => Some("1".to_string()),
+                _ => None,
+            };
+            match parse_with_env(args.iter().map(|a| a.to_string()), env) {
+                Ok(Command::Search(config)) => config,
+                _ => panic!("expected a search"),
+            }
+        };
+        let defaults = config(&["minigrep", "q"]);
+        assert_eq!((::Case::Smart, ::Mode::Literal, 2),
+                   (defaults.case, defaults.mode, defaults.before_context));
+        let given = config(&["minigrep", "-s", "-C0", "q"]);
+        assert_eq!((::Case::Sensitive, 0), (given.case, given.before_context));
+    }
+
+    #[test]
+    fn fuzzy_edits() {
+        assert!(matches!(command(&["minigrep", "--fuzzy", "2", "abc"]),
+                         Ok(Command::Search(_))));
+        assert!(command(&["minigrep", "--fuzzy", "3", "abc"]).is_err());
+        assert!(command(&["minigrep", "--fuzzy=1", "-e", "abc", "-e", "x"]).is_err());
+        assert!(command(&["minigrep", "--fuzzy", "5", "longer"]).is_err());
+    }
+
+    #[test]
+    fn threads_and_sort() {
+        let config = match command(&["minigrep", "-j3", "--sort", "path", "q"]) {
+            Ok(Command::Search(config)) => config,
+            _ => panic!("expected a search"),
+        };
+        assert_eq!((3, ::Sort::Path), (config.threads, config.sort));
+        assert!(command(&["minigrep", "-j0", "q"]).is_err());
+        assert!(command(&["minigrep", "--sort=size", "q"]).is_err());
+    }
+
+    #[test]
+    fn limits() {
+        let config = match command(&["minigrep", "-m2", "--max-total=5",
+                                     "--max-filesize", "1K", "q"]) {
+            Ok(Command::Search(config)) => config,
+            _ => panic!("expected a search"),
+        };
+        assert_eq!((Some(2), Some(5), Some(1024)),
+                   (config.max_count, config.max_total, config.filter.max_size));
+    }
+
+    #[test]
+    fn only_matching_and_format() {
+        let show = |args: &[&str]| match command(args) {
+            Ok(Command::Search(config)) => config.show,
+            _ => panic!("expected a search"),
+        };
+        assert_eq!(::Show::Line, show(&["minigrep", "q"]));
+        assert_eq!(::Show::Matches, show(&["minigrep", "--format=$1", "-o", "q"]));
+        assert_eq!(::Show::Format(String::from("$1 at $2")),
+                   show(&["minigrep", "-o", "--format", "$1 at $2", "q"]));
+        assert!(command(&["minigrep", "-ov", "q"]).is_err());
+        assert!(command(&["minigrep", "-o", "-r", "x", "q"]).is_err());
+    }
+
+    #[test]
+    fn help_and_version() {
+        assert!(matches!(command(&["minigrep", "-i", "--help"]),
+                         Ok(Command::Help)));
+        assert!(matches!(command(&["minigrep", "-V"]), Ok(Command::Version)));
+        assert!(matches!(command(&["minigrep", "-n"]),
+                         Err(Error::Argument(_))));
+    }
+
+    #[test]
+    fn several_patterns() {
+        let config = match command(&["minigrep", "-e", "one", "-etwo", "a"]) {
+            Ok(Command::Search(config)) => config,
+            _ => panic!("expected a search"),
+        };
+        assert_eq!(vec!["one", "two"], config.queries);
+        assert_eq!("one\ntwo", config.query);
+        assert_eq!(vec!["a"], config.paths);
+        assert!(matches!(command(&["minigrep", "-f", "/nonexistent/patterns"]),
+                         Err(Error::Io { .. })));
+    }
+
+    #[test]
+    fn color_choices() {
+        let color = |args: &[&str]| match command(args) {
+            Ok(Command::Search(config)) => config.color,
+            _ => panic!("expected a search"),
+        };
+        assert_eq!(::Color::Auto, color(&["minigrep", "q"]));
+        assert_eq!(::Color::Always, color(&["minigrep", "--color=always", "q"]));
+        assert_eq!(::Color::Never, color(&["minigrep", "--color", "never", "q"]));
+        assert!(matches!(command(&["minigrep", "--color=blue", "q"]),
+                         Err(Error::Argument(_))));
+    }
+
+    #[test]
+    fn usage_lists_options() {
+        let text = usage();
+        for opt in OPTIONS {
+            assert!(text.contains(&format!("--{}", opt.long)));
+        }
+        assert!(text.contains("  -A, --after-context NUM  "));
+    }
+}
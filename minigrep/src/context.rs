@@ -0,0 +1,19 @@
+//! Lines of output: matches and the lines shown around them.
+//!
+//! `stream::Searcher` decides which lines are context as it reads, so no
+//! line is printed twice and a separator is only needed between hunks.
+
+use Match;
+
+/// A line of output, either a match or a line shown for context.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Line<'a> {
+    /// Line number, starting at 1.
+    pub line_number: usize,
+    /// Byte offset of the start of the line in the searched text.
+    pub offset: usize,
+    /// The line as it was in the input, without its line terminator.
+    pub line: &'a [u8],
+    /// The match on this line, or `None` for a context line.
+    pub matched: Option<Match<'a>>,
+}
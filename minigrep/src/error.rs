@@ -0,0 +1,97 @@
+//! Errors reported by minigrep.
+
+use std::error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+use regex;
+
+/// Everything that can go wrong in a search.
+#[derive(Debug)]
+pub enum Error {
+    /// The command line could not be understood.
+    Argument(String),
+    /// A file or directory could not be read.
+    Io { path: PathBuf, error: io::Error },
+    /// A file is not valid UTF-8.
+    Encoding { path: PathBuf },
+    /// The query is not a valid regular expression.
+    Pattern(regex::Error),
+    /// The results could not be written.
+    Output(io::Error),
+}
+
+impl Error {
+    /// Attach `path` to an error from reading it.
+    pub fn from_io<P: Into<PathBuf>>(path: P, error: io::Error) -> Error {
+        if error.kind() == io::ErrorKind::InvalidData {
+            Error::Encoding { path: path.into() }
+        } else {
+            Error::Io { path: path.into(), error }
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Argument(ref message) => write!(f, "{}", message),
+            Error::Io { ref path, ref error } => {
+                write!(f, "{}: {}", path.display(), error)
+            },
+            Error::Encoding { ref path } => {
+                write!(f, "{}: stream did not contain valid UTF-8",
+                       path.display())
+            },
+            Error::Pattern(ref error) => write!(f, "{}", error),
+            Error::Output(ref error) => {
+                write!(f, "cannot write results: {}", error)
+            },
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Io { ref error, .. } | Error::Output(ref error) => Some(error),
+            Error::Pattern(ref error) => Some(error),
+            Error::Argument(_) | Error::Encoding { .. } => None,
+        }
+    }
+}
+
+impl From<regex::Error> for Error {
+    fn from(error: regex::Error) -> Error {
+        Error::Pattern(error)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::error::Error as StdError;
+
+    #[test]
+    fn io_errors_keep_the_path() {
+        let error = Error::from_io("poem.txt",
+            io::Error::new(io::ErrorKind::NotFound, "not found"));
+        assert_eq!("poem.txt: not found", error.to_string());
+        assert!(error.source().is_some());
+    }
+
+    #[test]
+    fn invalid_data_is_an_encoding_error() {
+        let error = Error::from_io("bin",
+            io::Error::new(io::ErrorKind::InvalidData, "bad"));
+        assert!(matches!(error, Error::Encoding { .. }));
+        assert_eq!("bin: stream did not contain valid UTF-8", error.to_string());
+    }
+
+    #[test]
+    fn pattern_errors_convert() {
+        let error: Error = regex::Regex::new("(").unwrap_err().into();
+        assert!(matches!(error, Error::Pattern(_)));
+    }
+}
@@ -0,0 +1,139 @@
+//! Unicode case folding for case-insensitive search.
+//!
+//! Two strings match without regard to case when their case foldings are
+//! equal.  Folding a character is lowercasing it, except for the characters
+//! in `FULL_FOLDS`, whose folding is not their lowercase form.  Some of those
+//! fold to several characters, so `ß` matches `SS` and `ﬁ` matches `FI`.
+//!
+//! Lines are folded one character at a time as they are compared, so
+//! nothing is allocated per line, and match spans refer to the original
+//! text.
+
+/// Characters whose folding differs from their lowercase form, sorted.
+static FULL_FOLDS: &[(char, &str)] = &[
+    ('\u{00DF}', "ss"),        // ß
+    ('\u{0130}', "i\u{0307}"), // İ
+    ('\u{0149}', "\u{02BC}n"), // ŉ
+    ('\u{017F}', "s"),         // ſ
+    ('\u{01F0}', "j\u{030C}"), // ǰ
+    ('\u{0345}', "\u{03B9}"),  // ypogegrammeni
+    ('\u{03C2}', "\u{03C3}"),  // ς
+    ('\u{03D0}', "\u{03B2}"),  // ϐ
+    ('\u{03D1}', "\u{03B8}"),  // ϑ
+    ('\u{03D5}', "\u{03C6}"),  // ϕ
+    ('\u{03D6}', "\u{03C0}"),  // ϖ
+    ('\u{03F0}', "\u{03BA}"),  // ϰ
+    ('\u{03F1}', "\u{03C1}"),  // ϱ
+    ('\u{03F5}', "\u{03B5}"),  // ϵ
+    ('\u{0587}', "\u{0565}\u{0582}"), // և
+    ('\u{1E96}', "h\u{0331}"), // ẖ
+    ('\u{1E97}', "t\u{0308}"), // ẗ
+    ('\u{1E98}', "w\u{030A}"), // ẘ
+    ('\u{1E99}', "y\u{030A}"), // ẙ
+    ('\u{1E9A}', "a\u{02BE}"), // ẚ
+    ('\u{1E9B}', "\u{1E61}"),  // ẛ
+    ('\u{1E9E}', "ss"),        // ẞ
+    ('\u{1FBE}', "\u{03B9}"),  // ι
+    ('\u{FB00}', "ff"),        // ﬀ
+    ('\u{FB01}', "fi"),        // ﬁ
+    ('\u{FB02}', "fl"),        // ﬂ
+    ('\u{FB03}', "ffi"),       // ﬃ
+    ('\u{FB04}', "ffl"),       // ﬄ
+    ('\u{FB05}', "st"),        // ﬅ
+    ('\u{FB06}', "st"),        // ﬆ
+];
+
+/// The case folding of `c`.
+pub fn fold(c: char) -> impl Iterator<Item = char> {
+    c.to_lowercase().flat_map(|l| {
+        let special = FULL_FOLDS.binary_search_by_key(&l, |f| f.0).ok()
+            .map(|i| FULL_FOLDS[i].1.chars());
+        let simple = match special {
+            Some(_) => None,
+            None => Some(l),
+        };
+        special.into_iter().flatten().chain(simple)
+    })
+}
+
+/// The case folding of `text`.
+pub fn fold_str(text: &str) -> Vec<char> {
+    text.chars().flat_map(fold).collect()
+}
+
+/// Find the folded `query` in `text` at or after byte `from`.
+///
+/// A match covers whole characters of `text`: `s` does not match half of
+/// `ß`.  The span returned is a byte range of `text`.
+pub fn find(query: &[char], text: &str, from: usize) -> Option<(usize, usize)> {
+    if query.is_empty() {
+        return Some((from, from));
+    }
+    for (start, _) in text[from..].char_indices() {
+        let start = from + start;
+        let mut wanted = query.iter();
+        for (i, c) in text[start..].char_indices() {
+            if !fold(c).all(|f| wanted.next() == Some(&f)) {
+                break;
+            }
+            if wanted.len() == 0 {
+                return Some((start, start + i + c.len_utf8()));
+            }
+        }
+    }
+    None
+}
+
+/// Check if `text` has an uppercase letter, ignoring the letter after each
+/// backslash when `escapes` is set, as in `\W`.
+pub fn has_uppercase(text: &str, escapes: bool) -> bool {
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if escapes && c == '\\' {
+            chars.next();
+        } else if c.is_uppercase() {
+            return true;
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn find_str(query: &str, text: &str) -> Option<(usize, usize)> {
+        find(&fold_str(query), text, 0)
+    }
+
+    #[test]
+    fn folds_that_change_length() {
+        assert_eq!(Some((4, 11)), find_str("strasse", "Die STRAßE hier"));
+        assert_eq!(Some((4, 11)), find_str("STRAẞE", "Die straße"));
+        assert_eq!(Some((0, 5)), find_str("FILE", "\u{FB01}le"));
+        assert_eq!(None, find_str("s", "ß"));
+    }
+
+    #[test]
+    fn folds_to_the_same_letter() {
+        assert_eq!(Some((0, 10)), find_str("ΣΟΦΟΣ", "σοφος"));
+        assert_eq!(Some((0, 3)), find_str("k", "\u{212A}"));
+        assert_eq!(Some((0, 2)), find_str("i\u{0307}", "İ"));
+    }
+
+    #[test]
+    fn search_starts_at_from() {
+        let query = fold_str("ab");
+        assert_eq!(Some((3, 5)), find(&query, "AB AB", 1));
+        assert_eq!(Some((2, 2)), find(&[], "AB", 2));
+    }
+
+    #[test]
+    fn uppercase_queries() {
+        assert!(has_uppercase("Rust", false));
+        assert!(!has_uppercase("rust", false));
+        assert!(!has_uppercase("straße", false));
+        assert!(!has_uppercase(r"\w+\Sfoo", true));
+        assert!(has_uppercase(r"\w+\SFoo", true));
+    }
+}
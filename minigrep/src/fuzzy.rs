@@ -0,0 +1,242 @@
+//! Approximate matching, allowing a few edits.
+//!
+//! A query matches text within `k` edits if inserting, deleting or
+//! substituting at most `k` characters of the query makes it appear in the
+//! text.  Queries of up to 64 characters are matched with the bit-parallel
+//! algorithm of Wu and Manber, which keeps, for each number of edits, a word
+//! whose bits say which prefixes of the query end at the current character.
+//! Longer queries fall back to Sellers' dynamic programming.
+//!
+//! Either way the text is read up to the first stretch of it that matches,
+//! to find where the closest match in that stretch ends, and its start is
+//! then found by matching backwards from there.
+
+/// Most edits a search may allow.
+pub const MAX_EDITS: usize = 4;
+
+/// A match of a query in some text.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FuzzyMatch {
+    /// Number of edits needed.
+    pub distance: usize,
+    /// Byte range of the match in the text.
+    pub start: usize,
+    pub end: usize,
+}
+
+/// A query to match within a number of edits.
+pub struct Fuzzy {
+    pattern: Vec<char>,
+    /// Bit `i` of a character's mask is set if the pattern has that
+    /// character at `i`; sorted by character.
+    masks: Vec<(char, u64)>,
+    max: usize,
+    case_insensitive: bool,
+}
+
+impl Fuzzy {
+    /// Matcher for `query` within `max` edits.
+    ///
+    /// Case-insensitive matchers compare characters by their simple
+    /// lowercase forms.
+    pub fn new(query: &str, max: usize, case_insensitive: bool) -> Fuzzy {
+        let pattern: Vec<char> = query.chars()
+            .map(|c| if case_insensitive { lower(c) } else { c })
+            .collect();
+        let mut masks: Vec<(char, u64)> = Vec::new();
+        for (i, &c) in pattern.iter().enumerate().take(64) {
+            match masks.binary_search_by_key(&c, |m| m.0) {
+                Ok(at) => masks[at].1 |= 1 << i,
+                Err(at) => masks.insert(at, (c, 1 << i)),
+            }
+        }
+        Fuzzy { pattern, masks, max, case_insensitive }
+    }
+
+    fn key(&self, c: char) -> char {
+        if self.case_insensitive { lower(c) } else { c }
+    }
+
+    fn mask(&self, c: char) -> u64 {
+        match self.masks.binary_search_by_key(&c, |m| m.0) {
+            Ok(at) => self.masks[at].1,
+            Err(_) => 0,
+        }
+    }
+
+    /// Find the leftmost match in `text`: of the matches ending one after
+    /// another where the query first matches, the one with the fewest
+    /// edits, the first one if several tie.
+    ///
+    /// A query that `max` edits could delete whole would match anywhere,
+    /// without matching anything, so it is taken to match nowhere.
+    pub fn find(&self, text: &str) -> Option<FuzzyMatch> {
+        if self.pattern.len() <= self.max {
+            return None;
+        }
+        let (distance, end) = if self.pattern.len() <= 64 {
+            self.first_end_bitap(text)?
+        } else {
+            self.first_end_dp(text)?
+        };
+        let start = self.start(text, end, distance);
+        Some(FuzzyMatch { distance, start, end })
+    }
+
+    /// Fewest edits of the first match and where it ends, by Wu-Manber.
+    fn first_end_bitap(&self, text: &str) -> Option<(usize, usize)> {
+        let last = 1u64 << (self.pattern.len() - 1);
+        // r[d]: prefixes of the pattern matching here with d edits or fewer.
+        let mut r: Vec<u64> = (0..self.max + 1).map(|d| (1u64 << d) - 1).collect();
+        let mut best: Option<(usize, usize)> = None;
+        for (i, c) in text.char_indices() {
+            let mask = self.mask(self.key(c));
+            let mut old = r[0];
+            r[0] = ((r[0] << 1) | 1) & mask;
+            for d in 1..r.len() {
+                let previous = r[d];
+                // Match, insertion, substitution and deletion, in that order.
+                r[d] = (((previous << 1) | 1) & mask) | old | (old << 1)
+                    | (r[d - 1] << 1) | 1;
+                old = previous;
+            }
+            let d = r.iter().position(|&bits| bits & last != 0);
+            if closest(&mut best, d, i + c.len_utf8()) {
+                break;
+            }
+        }
+        best
+    }
+
+    /// Fewest edits of the first match and where it ends, by Sellers.
+    fn first_end_dp(&self, text: &str) -> Option<(usize, usize)> {
+        let m = self.pattern.len();
+        // column[i]: edits to match the first i pattern characters ending here.
+        let mut column: Vec<usize> = (0..m + 1).collect();
+        let mut best: Option<(usize, usize)> = None;
+        for (i, c) in text.char_indices() {
+            let key = self.key(c);
+            let mut diagonal = column[0];
+            for j in 1..m + 1 {
+                let cost = if self.pattern[j - 1] == key { 0 } else { 1 };
+                let value = (diagonal + cost)
+                    .min(column[j] + 1)
+                    .min(column[j - 1] + 1);
+                diagonal = column[j];
+                column[j] = value;
+            }
+            let d = Some(column[m]).filter(|&d| d <= self.max);
+            if closest(&mut best, d, i + c.len_utf8()) {
+                break;
+            }
+        }
+        best
+    }
+
+    /// Start of the shortest match of `distance` edits ending at `end`.
+    fn start(&self, text: &str, end: usize, distance: usize) -> usize {
+        let window: Vec<(usize, char)> = text[..end].char_indices().rev()
+            .take(self.pattern.len() + distance)
+            .collect();
+        // Match the reversed pattern against the text before `end`.
+        let m = self.pattern.len();
+        let mut column: Vec<usize> = (0..m + 1).collect();
+        for (j, &(i, c)) in window.iter().enumerate() {
+            let c = self.key(c);
+            let mut diagonal = column[0];
+            column[0] = j + 1;
+            for k in 1..m + 1 {
+                let cost = if self.pattern[m - k] == c { 0 } else { 1 };
+                let value = (diagonal + cost)
+                    .min(column[k] + 1)
+                    .min(column[k - 1] + 1);
+                diagonal = column[k];
+                column[k] = value;
+            }
+            if column[m] == distance {
+                return i;
+            }
+        }
+        window.last().map_or(end, |w| w.0)
+    }
+}
+
+/// Keep in `best` the fewest edits of a match and where it ends, given
+/// that a match ending at `end` needs `d` edits, or none ends there.
+///
+/// Returns true once the stretch of text where matches end, one after
+/// another, is over, or an exact match is found.
+fn closest(best: &mut Option<(usize, usize)>, d: Option<usize>, end: usize)
+    -> bool {
+    match (*best, d) {
+        (None, None) => false,
+        (_, None) => true,
+        (Some(b), Some(d)) if d >= b.0 => false,
+        (_, Some(d)) => {
+            *best = Some((d, end));
+            d == 0
+        },
+    }
+}
+
+fn lower(c: char) -> char {
+    c.to_lowercase().next().unwrap_or(c)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn find(query: &str, max: usize, text: &str) -> Option<(usize, usize, usize)> {
+        Fuzzy::new(query, max, false).find(text)
+            .map(|m| (m.distance, m.start, m.end))
+    }
+
+    #[test]
+    fn exact_matches() {
+        assert_eq!(Some((0, 1, 5)), find("rust", 1, "Trust me"));
+        assert_eq!(None, find("rust", 0, "Trsut me"));
+    }
+
+    #[test]
+    fn each_kind_of_edit() {
+        // Substitution, insertion and deletion.
+        assert_eq!(Some((1, 0, 6)), find("search", 1, "saarch"));
+        assert_eq!(Some((1, 0, 7)), find("search", 1, "seaarch"));
+        assert_eq!(Some((1, 0, 5)), find("search", 1, "serch"));
+        assert_eq!(Some((2, 4, 10)), find("search", 2, "the sarech"));
+        assert_eq!(None, find("search", 1, "the sarech"));
+    }
+
+    #[test]
+    fn leftmost_at_its_closest() {
+        // "du" is the first match, but "dust" in the same place is closer.
+        assert_eq!(Some((1, 0, 4)), find("duct", 2, "dust, duct"));
+        assert_eq!(Some((0, 6, 10)), find("duct", 0, "dust, duct"));
+        assert_eq!(Some((0, 0, 6)), find("search", 2, "search"));
+    }
+
+    #[test]
+    fn queries_edits_could_delete_match_nowhere() {
+        assert_eq!(None, find("abc", 3, "abc"));
+        assert_eq!(None, find("ab", 4, "xx"));
+    }
+
+    #[test]
+    fn long_queries() {
+        let query = "a".repeat(70);
+        let text = format!("x{}y{}", "a".repeat(35), "a".repeat(34));
+        assert_eq!(Some((1, 1, 71)), find(&query, 2, &text));
+    }
+
+    #[test]
+    fn case_insensitive() {
+        let m = Fuzzy::new("RUST", 1, true).find("the rost");
+        assert_eq!(Some(FuzzyMatch { distance: 1, start: 4, end: 8 }), m);
+        // The Kelvin sign folds to a shorter 'k'; the match ends after it.
+        let query = format!("{}k", "a".repeat(65));
+        let text = format!("{}\u{212A}zz", "a".repeat(65));
+        let m = Fuzzy::new(&query, 1, true).find(&text);
+        assert_eq!(Some(FuzzyMatch { distance: 0, start: 0, end: 68 }), m);
+    }
+}
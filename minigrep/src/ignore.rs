@@ -0,0 +1,274 @@
+//! Globs and ignore files, as git reads `.gitignore`.
+//!
+//! A glob matches a path relative to some directory, using `/` between
+//! components.  `*` matches anything but `/`, `?` one character but `/`,
+//! `[a-z]` a character in a set (`[!a-z]` or `[^a-z]` one not in it), and
+//! `\` makes the next character literal.  `**/` matches any number of
+//! directories, and a trailing `/**` everything inside a directory.
+//!
+//! A glob with no `/` but at its end matches the last component of a path,
+//! at any depth; otherwise it is anchored to the directory it is relative
+//! to.  A trailing `/` matches only directories.
+
+use std::fs;
+use std::path::Path;
+
+/// Names of the ignore files read in each directory, in increasing order
+/// of precedence.
+pub static IGNORE_FILES: &[&str] = &[".gitignore", ".ignore"];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Char(char),
+    /// `?`
+    Any,
+    /// `*`
+    Star,
+    /// `**/`: nothing, or anything ending with `/`.
+    Dirs,
+    /// `**` at the end: anything at all.
+    Everything,
+    /// `[...]`: ranges of characters, and whether the set is negated.
+    Class(Vec<(char, char)>, bool),
+}
+
+/// A glob, with how it is applied to paths.
+#[derive(Debug, Clone)]
+pub struct Rule {
+    tokens: Vec<Token>,
+    /// Matches paths that the glob does not, as `!` in ignore files does.
+    pub negated: bool,
+    dir_only: bool,
+    anchored: bool,
+}
+
+impl Rule {
+    /// Rule for `glob`, which may start with `!` when `negation` is set.
+    pub fn new(glob: &str, negation: bool) -> Rule {
+        let (negated, glob) = match glob.strip_prefix('!') {
+            Some(rest) if negation => (true, rest),
+            _ => (false, glob),
+        };
+        let (dir_only, glob) = match glob.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, glob),
+        };
+        let anchored = glob.contains('/');
+        let glob = glob.strip_prefix('/').unwrap_or(glob);
+        Rule { tokens: parse(glob), negated, dir_only, anchored }
+    }
+
+    /// Check if the glob matches `path`, given relative to the directory
+    /// the rule belongs to.
+    pub fn matches(&self, path: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        let path = if self.anchored {
+            path
+        } else {
+            path.rsplit('/').next().unwrap_or(path)
+        };
+        let text: Vec<char> = path.chars().collect();
+        matches(&self.tokens, &text)
+    }
+}
+
+fn parse(glob: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let chars: Vec<char> = glob.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        i += 1;
+        match c {
+            '\\' if i < chars.len() => {
+                tokens.push(Token::Char(chars[i]));
+                i += 1;
+            },
+            '?' => tokens.push(Token::Any),
+            '*' if chars.get(i) == Some(&'*') => {
+                let at_start = i == 1 || chars[i - 2] == '/';
+                i += 1;
+                if at_start && chars.get(i) == Some(&'/') {
+                    tokens.push(Token::Dirs);
+                    i += 1;
+                } else if at_start && i == chars.len() {
+                    tokens.push(Token::Everything);
+                } else {
+                    tokens.push(Token::Star);
+                }
+            },
+            '*' => tokens.push(Token::Star),
+            '[' => match class(&chars[i..]) {
+                Some((token, len)) => {
+                    tokens.push(token);
+                    i += len;
+                },
+                None => tokens.push(Token::Char('[')),
+            },
+            c => tokens.push(Token::Char(c)),
+        }
+    }
+    tokens
+}
+
+/// Parse a class after its `[`, returning it and the characters used.
+fn class(chars: &[char]) -> Option<(Token, usize)> {
+    let mut i = 0;
+    let negated = matches!(chars.first(), Some(&'!') | Some(&'^'));
+    if negated {
+        i += 1;
+    }
+    let mut ranges = Vec::new();
+    let mut first = true;
+    loop {
+        let c = *chars.get(i)?;
+        if c == ']' && !first {
+            return Some((Token::Class(ranges, negated), i + 1));
+        }
+        first = false;
+        if chars.get(i + 1) == Some(&'-') && chars.get(i + 2).is_some_and(|&e| e != ']') {
+            ranges.push((c, chars[i + 2]));
+            i += 3;
+        } else {
+            ranges.push((c, c));
+            i += 1;
+        }
+    }
+}
+
+fn matches(tokens: &[Token], text: &[char]) -> bool {
+    let (token, rest) = match tokens.split_first() {
+        Some(split) => split,
+        None => return text.is_empty(),
+    };
+    match *token {
+        Token::Char(c) => text.first() == Some(&c) && matches(rest, &text[1..]),
+        Token::Any => {
+            text.first().is_some_and(|&c| c != '/') && matches(rest, &text[1..])
+        },
+        Token::Class(ref ranges, negated) => {
+            text.first().is_some_and(|&c| {
+                c != '/' && ranges.iter().any(|r| r.0 <= c && c <= r.1) != negated
+            }) && matches(rest, &text[1..])
+        },
+        Token::Star => {
+            let run = text.iter().position(|&c| c == '/').unwrap_or(text.len());
+            (0..run + 1).any(|i| matches(rest, &text[i..]))
+        },
+        Token::Dirs => {
+            (0..text.len() + 1)
+                .filter(|&i| i == 0 || text[i - 1] == '/')
+                .any(|i| matches(rest, &text[i..]))
+        },
+        Token::Everything => true,
+    }
+}
+
+/// The rules of the ignore files in one directory.
+#[derive(Debug, Clone, Default)]
+pub struct Ignore {
+    rules: Vec<Rule>,
+}
+
+impl Ignore {
+    /// Parse the text of an ignore file.
+    ///
+    /// Blank lines and lines starting with `#` are skipped, as are trailing
+    /// spaces unless escaped with `\`.
+    pub fn parse(text: &str) -> Ignore {
+        let rules = text.lines()
+            .map(|line| {
+                let trimmed = line.trim_end();
+                if line.len() > trimmed.len() && trimmed.ends_with('\\') {
+                    &line[..trimmed.len() + 1]
+                } else {
+                    trimmed
+                }
+            })
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| Rule::new(line, true))
+            .collect();
+        Ignore { rules }
+    }
+
+    /// Read the ignore files in `dir`, if it has any.
+    pub fn from_dir(dir: &Path) -> Option<Ignore> {
+        let mut ignore = Ignore::default();
+        for name in IGNORE_FILES {
+            if let Ok(text) = fs::read_to_string(dir.join(name)) {
+                ignore.rules.extend(Ignore::parse(&text).rules);
+            }
+        }
+        if ignore.rules.is_empty() { None } else { Some(ignore) }
+    }
+
+    /// Whether `path`, relative to the ignore file's directory, is ignored:
+    /// `Some(true)` if so, `Some(false)` if a negated rule lets it through,
+    /// and `None` if no rule applies.  The last rule to match wins.
+    pub fn ignores(&self, path: &str, is_dir: bool) -> Option<bool> {
+        self.rules.iter().rev()
+            .find(|rule| rule.matches(path, is_dir))
+            .map(|rule| !rule.negated)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn glob(glob: &str, path: &str) -> bool {
+        Rule::new(glob, false).matches(path, false)
+    }
+
+    #[test]
+    fn wildcards_and_classes() {
+        assert!(glob("*.rs", "src/lib.rs"));
+        assert!(!glob("*.rs", "src/lib.rs.bak"));
+        assert!(glob("lib.?s", "lib.rs"));
+        assert!(glob("[a-c]x", "bx"));
+        assert!(!glob("[!a-c]x", "bx"));
+        assert!(glob("[]]", "]"));
+        assert!(glob("\\*", "*"));
+        assert!(!glob("\\*", "a"));
+    }
+
+    #[test]
+    fn anchoring() {
+        assert!(glob("src/*.rs", "src/lib.rs"));
+        assert!(!glob("src/*.rs", "a/src/lib.rs"));
+        assert!(!glob("src/*.rs", "src/a/lib.rs"));
+        assert!(glob("/lib.rs", "lib.rs"));
+        assert!(!glob("/lib.rs", "src/lib.rs"));
+    }
+
+    #[test]
+    fn double_stars() {
+        assert!(glob("**/test/*.rs", "test/a.rs"));
+        assert!(glob("**/test/*.rs", "x/y/test/a.rs"));
+        assert!(glob("a/**/b", "a/b"));
+        assert!(glob("a/**/b", "a/x/y/b"));
+        assert!(glob("a/**", "a/x/y"));
+        assert!(!glob("a/**", "b/x"));
+    }
+
+    #[test]
+    fn ignore_files() {
+        let ignore = Ignore::parse(&(String::from("\
+# build output
+target/
+*.log
+!keep.log
+/only-here
+") + "escaped\\ \n"));
+        assert_eq!(Some(true), ignore.ignores("target", true));
+        assert_eq!(None, ignore.ignores("target", false));
+        assert_eq!(Some(true), ignore.ignores("a/b/debug.log", false));
+        assert_eq!(Some(false), ignore.ignores("a/keep.log", false));
+        assert_eq!(Some(true), ignore.ignores("only-here", false));
+        assert_eq!(None, ignore.ignores("a/only-here", false));
+        assert_eq!(Some(true), ignore.ignores("escaped ", false));
+        assert_eq!(None, ignore.ignores("main.rs", false));
+    }
+}
@@ -0,0 +1,94 @@
+//! Just enough JSON to write search results as JSON Lines.
+//!
+//! Text that may not be UTF-8, such as file names and lines, is written as
+//! an object: `{"text":"..."}` when it is valid UTF-8, or `{"bytes":"..."}`
+//! holding the raw bytes in standard base64 when it is not.
+
+use std::path::Path;
+
+static BASE64: &[u8] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// `s` as a JSON string, quotes included.
+pub fn string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 || c == '\u{7F}' => {
+                out.push_str(&format!("\\u{:04x}", c as u32));
+            },
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// `bytes` as a `text` or `bytes` object.
+pub fn data(bytes: &[u8]) -> String {
+    match ::std::str::from_utf8(bytes) {
+        Ok(text) => format!("{{\"text\":{}}}", string(text)),
+        Err(_) => format!("{{\"bytes\":\"{}\"}}", base64(bytes)),
+    }
+}
+
+/// The bytes of `path` as a `text` or `bytes` object.
+#[cfg(unix)]
+pub fn path(path: &Path) -> String {
+    use std::os::unix::ffi::OsStrExt;
+    data(path.as_os_str().as_bytes())
+}
+
+#[cfg(not(unix))]
+pub fn path(path: &Path) -> String {
+    data(path.to_string_lossy().as_bytes())
+}
+
+/// Standard base64 of `bytes`, with padding.
+pub fn base64(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64[(n >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn escapes_strings() {
+        assert_eq!(r#""a \"b\" \\ \n\t\u0001""#, string("a \"b\" \\ \n\t\u{1}"));
+        assert_eq!("\"straße\"", string("straße"));
+    }
+
+    #[test]
+    fn text_or_bytes() {
+        assert_eq!(r#"{"text":"ok"}"#, data(b"ok"));
+        assert_eq!(r#"{"bytes":"b2X/"}"#, data(b"oe\xFF"));
+    }
+
+    #[test]
+    fn base64_padding() {
+        assert_eq!("", base64(b""));
+        assert_eq!("Zg==", base64(b"f"));
+        assert_eq!("Zm8=", base64(b"fo"));
+        assert_eq!("Zm9v", base64(b"foo"));
+        assert_eq!("Zm9vYg==", base64(b"foob"));
+    }
+}
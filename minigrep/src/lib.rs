@@ -1,70 +1,914 @@
-use std::env;
-use std::error::Error;
+pub mod aho_corasick;
+pub mod args;
+pub mod context;
+pub mod error;
+pub mod fold;
+pub mod fuzzy;
+pub mod ignore;
+pub mod json;
+pub mod matcher;
+pub mod memchr;
+pub mod mmap;
+pub mod printer;
+pub mod regex;
+pub mod replace;
+pub mod stream;
+pub mod threadpool;
+pub mod utf16;
+pub mod walk;
+
+use std::borrow::Cow;
+use std::fs;
 use std::fs::File;
+use std::io;
 use std::io::prelude::*;
+use std::iter;
+use std::path::{Path, PathBuf};
+use std::str;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+
+pub use error::Error;
+
+use ignore::Rule;
+use matcher::{CaseInsensitive, Literal, Matcher, Matches};
+use mmap::Mmap;
+use printer::{FileOutput, Out, Pipe, Printer};
+use replace::Template;
+use stream::{Budget, Output, Searcher};
+use threadpool::ThreadPool;
+use walk::{Filter, Walk};
+
+/// Name shown for results read from standard input.
+const STDIN_NAME: &str = "(standard input)";
+
+/// How the query is interpreted.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    /// The query is a plain string.
+    Literal,
+    /// The query is a regular expression.
+    Regex,
+    /// The query is a plain string that may be found with up to this many
+    /// edits, at most `fuzzy::MAX_EDITS`.
+    Fuzzy(usize),
+}
+
+/// Whether letter case matters when searching.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Case {
+    Sensitive,
+    Insensitive,
+    /// Insensitive unless the query has an uppercase letter.
+    Smart,
+}
+
+impl Case {
+    /// Decide whether a search for `query` in `mode` is case-sensitive.
+    pub fn is_sensitive(self, query: &str, mode: Mode) -> bool {
+        match self {
+            Case::Sensitive => true,
+            Case::Insensitive => false,
+            Case::Smart => fold::has_uppercase(query, mode == Mode::Regex),
+        }
+    }
+}
+
+/// What is reported for each file searched.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputMode {
+    /// Each matching line.
+    Lines,
+    /// The number of matching lines.
+    Count,
+    /// The file name, if any line matches.
+    FilesWithMatches,
+    /// The file name, if no line matches.
+    FilesWithoutMatch,
+    /// Nothing; only the exit status tells if anything matched.
+    Quiet,
+    /// Each matching line and line of context as JSON, between events
+    /// for the start and end of each file.
+    Json,
+}
+
+/// What is shown of each matching line.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Show {
+    /// The whole line.
+    Line,
+    /// Each match, on a line of its own.
+    Matches,
+    /// A template expanded for each match, on a line of its own; `$1`
+    /// stands for what the first group matched.
+    Format(String),
+}
+
+/// What must surround a match for it to count.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Boundary {
+    /// Anything.
+    Any,
+    /// Characters that are not letters, digits or `_`, or the line's ends.
+    Word,
+    /// The ends of the line: the match is the whole line.
+    Line,
+    /// One of these characters, or the line's ends.
+    Chars(String),
+}
+
+impl Boundary {
+    /// Check if the match of `start..end` in `text`, one line or many, has
+    /// the boundary.
+    pub fn allows(&self, text: &[u8], start: usize, end: usize) -> bool {
+        self.starts(text, start) && self.ends(text, end)
+    }
+
+    /// Check if a match may start at byte `at` of `text`.
+    pub fn starts(&self, text: &[u8], at: usize) -> bool {
+        match *self {
+            Boundary::Any => true,
+            Boundary::Line => at == 0 || text[at - 1] == b'\n',
+            _ => self.is_boundary(char_before(text, at)),
+        }
+    }
+
+    /// Check if a match may end at byte `at` of `text`.
+    pub fn ends(&self, text: &[u8], at: usize) -> bool {
+        match *self {
+            Boundary::Any => true,
+            Boundary::Line => {
+                let rest = &text[at..];
+                rest.is_empty() || rest.starts_with(b"\n")
+                    || rest.starts_with(b"\r\n")
+            },
+            _ => self.is_boundary(char_after(text, at)),
+        }
+    }
+
+    /// Check if `c`, or the end of the text for `None`, may be next to a
+    /// match.  Bytes that are not UTF-8 count as `None`.
+    fn is_boundary(&self, c: Option<char>) -> bool {
+        match *self {
+            Boundary::Word => !c.is_some_and(|c| c.is_alphanumeric() || c == '_'),
+            Boundary::Chars(ref chars) => c.is_none_or(|c| chars.contains(c)),
+            Boundary::Any | Boundary::Line => true,
+        }
+    }
+}
+
+/// The character that ends at byte `at` of `text`.
+fn char_before(text: &[u8], at: usize) -> Option<char> {
+    (at.saturating_sub(4)..at)
+        .filter_map(|i| str::from_utf8(&text[i..at]).ok())
+        .next()
+        .and_then(|s| s.chars().next_back())
+}
+
+/// The character that starts at byte `at` of `text`.
+fn char_after(text: &[u8], at: usize) -> Option<char> {
+    let bytes = &text[at..text.len().min(at + 4)];
+    let valid = match str::from_utf8(bytes) {
+        Ok(s) => s,
+        Err(e) => str::from_utf8(&bytes[..e.valid_up_to()]).unwrap(),
+    };
+    valid.chars().next()
+}
+
+/// What to do with binary files, those with a NUL byte.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Binary {
+    /// Say whether the file matches, without showing any lines.
+    Report,
+    /// Search them as text.
+    Text,
+    /// Treat them as not matching.  A NUL found only after some lines were
+    /// shown stops the search, and those lines still count as matches.
+    Skip,
+}
+
+/// When to highlight the output with ANSI colors.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Color {
+    /// When writing to a terminal and `NO_COLOR` is not set.
+    Auto,
+    Always,
+    Never,
+}
+
+/// The order files are printed in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Sort {
+    /// As soon as each has been searched.
+    None,
+    /// In the order they are found, with directories read in sorted order.
+    Path,
+}
+
+/// What a search found, which decides the exit status.
+#[derive(Debug, Default)]
+pub struct Status {
+    /// Some line matched.
+    pub matched: bool,
+    /// Paths that could not be searched.
+    pub errors: Vec<Error>,
+}
+
+impl Status {
+    /// Exit status as grep reports it: 0 if something matched, 1 if
+    /// nothing did, and 2 on errors, unless `quiet` and something matched.
+    pub fn exit_code(&self, quiet: bool) -> i32 {
+        match (self.matched, !self.errors.is_empty()) {
+            (true, true) if quiet => 0,
+            (_, true) => 2,
+            (true, false) => 0,
+            (false, false) => 1,
+        }
+    }
+}
+
+/// A matching line and where the match was found.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Match<'a> {
+    /// Line number, starting at 1.
+    pub line_number: usize,
+    /// Byte offset of the start of the line in the searched text.
+    pub offset: usize,
+    /// Byte range of the first match within the line.
+    pub start: usize,
+    pub end: usize,
+    /// Index in `Config::queries` of the pattern of the first match.
+    pub pattern: usize,
+    /// Fewest edits needed by a match in the line; only fuzzy matches
+    /// need any.
+    pub distance: usize,
+    /// Byte ranges of every match within the line, in order.
+    pub spans: Vec<(usize, usize)>,
+    /// The line as it was in the input, without its line terminator;
+    /// borrowed from the text searched, or owned if it was read.
+    pub line: Cow<'a, [u8]>,
+}
+
+impl<'a> Match<'a> {
+    /// Column of the match, counting bytes from 1.
+    pub fn column(&self) -> usize {
+        self.start + 1
+    }
+}
 
 pub struct Config {
+    /// The patterns, one per line.
     pub query: String,
-    pub filename: String,
+    pub queries: Vec<String>,
+    pub paths: Vec<String>,
+    pub case: Case,
+    /// Whether this query is searched case-sensitively, given `case`.
     pub case_sensitive: bool,
-    pub search_fn: for <'r, 's> fn(&'r str, &'s str) -> Vec<&'s str>,
+    pub mode: Mode,
+    pub follow_links: bool,
+    pub max_depth: Option<usize>,
+    /// What to leave out when searching directories.
+    pub filter: Filter,
+    pub line_number: bool,
+    pub column: bool,
+    pub byte_offset: bool,
+    pub before_context: usize,
+    pub after_context: usize,
+    pub invert: bool,
+    /// Stop reading a file after this many matching lines.
+    pub max_count: Option<usize>,
+    /// Stop the whole search after this many matching lines.
+    pub max_total: Option<usize>,
+    /// Match against the whole of each file, so matches may span lines.
+    pub multiline: bool,
+    pub boundary: Boundary,
+    pub output: OutputMode,
+    pub show: Show,
+    /// Replacement for each match; the changes are shown as a diff.
+    pub replace: Option<String>,
+    /// Write the replacements to the files instead of showing them.
+    pub in_place: bool,
+    pub binary: Binary,
+    pub color: Color,
+    pub verbose: bool,
+    /// Map big files into memory rather than read them.
+    pub mmap: bool,
+    /// Number of files searched at once.
+    pub threads: usize,
+    pub sort: Sort,
+    /// What to search for, made from the queries; any `Matcher` that can
+    /// be shared between threads will do.
+    pub matcher: Box<dyn Matcher + Send + Sync>,
 }
 
 impl Config {
-    pub fn new(mut args: std::env::Args) -> Result<Config, &'static str> {
-        args.next();
+    /// Build a configuration from parsed command-line options.
+    pub fn new(matches: &args::Matches) -> Result<Config, Error> {
+        let multiline = matches.flag("multiline");
+        let mut free = matches.free.iter().cloned();
+        let mut queries: Vec<String> = matches.values("regexp").iter()
+            .map(|q| q.to_string())
+            .collect();
+        for path in matches.values("file") {
+            let patterns = fs::read_to_string(path)
+                .map_err(|e| Error::from_io(path, e))?;
+            queries.extend(patterns.lines().map(String::from));
+        }
+        // Without -e or -f, the first argument is the query, and each of
+        // its lines a pattern unless patterns may span lines.
+        if queries.is_empty() {
+            match free.next() {
+                Some(arg) if multiline => queries.push(arg),
+                Some(arg) => queries.extend(arg.split('\n').map(String::from)),
+                None => return Err(Error::Argument(
+                    String::from("Didn't get a query string"))),
+            }
+        }
+        let query = queries.join("\n");
+        // With no file name, or "-", read standard input.
+        let mut paths: Vec<String> = free.collect();
+        if paths.is_empty() {
+            paths.push(String::from("-"));
+        }
 
-        let query = match args.next() {
-            Some(arg) => arg,
-            None => return Err("Didn't get a query string"),
+        let case = match matches.last_of(&["ignore-case", "case-sensitive",
+                                           "smart-case"]) {
+            Some("ignore-case") => Case::Insensitive,
+            Some("smart-case") => Case::Smart,
+            _ => Case::Sensitive,
+        };
+        let mode = match matches.last_of(&["regex", "fixed-strings", "fuzzy"]) {
+            Some("regex") => Mode::Regex,
+            Some("fuzzy") => match matches.number("fuzzy")? {
+                Some(k) if k <= fuzzy::MAX_EDITS => Mode::Fuzzy(k),
+                _ => return Err(Error::Argument(format!(
+                    "invalid value for '--fuzzy': at most {} edits are allowed",
+                    fuzzy::MAX_EDITS))),
+            },
+            _ => Mode::Literal,
+        };
+        if let Mode::Fuzzy(k) = mode {
+            if let Some(query) = queries.iter().find(|q| q.chars().count() <= k) {
+                return Err(Error::Argument(format!(
+                    "invalid value for '--fuzzy': {} edits could delete all of \
+                     the pattern '{}'", k, query)));
+            }
+        }
+        if multiline && matches!(mode, Mode::Fuzzy(_)) {
+            return Err(Error::Argument(String::from(
+                "'--multiline' cannot be used with '--fuzzy'")));
+        }
+        let case_sensitive = case.is_sensitive(&query, mode);
+        let boundary = match matches.last_of(&["word-regexp", "line-regexp",
+                                               "boundary"]) {
+            Some("word-regexp") => Boundary::Word,
+            Some("line-regexp") => Boundary::Line,
+            Some(_) => Boundary::Chars(
+                matches.value("boundary").unwrap_or("").to_string()),
+            None => Boundary::Any,
+        };
+        let matcher = matcher::new(&queries, mode, case, multiline, &boundary)?;
+        let follow_links = matches.flag("follow");
+        let max_depth = matches.number("max-depth")?;
+        let mut filter = Filter::none();
+        filter.max_size = matches.size("max-filesize")?;
+        filter.hidden = matches.flag("hidden");
+        filter.ignore_files = !matches.flag("no-ignore");
+        for glob in matches.values("glob") {
+            match glob.strip_prefix('!') {
+                Some(glob) => filter.exclude.push(Rule::new(glob, false)),
+                None => filter.include.push(Rule::new(glob, false)),
+            }
+        }
+        for glob in matches.values("exclude") {
+            filter.exclude.push(Rule::new(glob, false));
+        }
+        for name in matches.values("type") {
+            filter.add_type(name, false)?;
+        }
+        for name in matches.values("type-not") {
+            filter.add_type(name, true)?;
+        }
+        let line_number = matches.flag("line-number");
+        let column = matches.flag("column");
+        let byte_offset = matches.flag("byte-offset");
+        let context = matches.number("context")?.unwrap_or(0);
+        let before_context = matches.number("before-context")?.unwrap_or(context);
+        let after_context = matches.number("after-context")?.unwrap_or(context);
+        let invert = matches.flag("invert-match");
+        let max_count = matches.number("max-count")?;
+        let max_total = matches.number("max-total")?;
+        let output = if matches.flag("quiet") {
+            OutputMode::Quiet
+        } else {
+            match matches.last_of(&["count", "files-with-matches",
+                                    "files-without-match", "json"]) {
+                Some("count") => OutputMode::Count,
+                Some("json") => OutputMode::Json,
+                Some("files-with-matches") => OutputMode::FilesWithMatches,
+                Some(_) => OutputMode::FilesWithoutMatch,
+                None => OutputMode::Lines,
+            }
         };
-        let filename = match args.next() {
-            Some(arg) => arg,
-            None => return Err("Didn't get a file name"),
+        let show = match matches.last_of(&["only-matching", "format"]) {
+            Some("only-matching") => Show::Matches,
+            Some(_) => Show::Format(
+                matches.value("format").unwrap_or("").to_string()),
+            None => Show::Line,
         };
+        if show != Show::Line && invert {
+            return Err(Error::Argument(String::from(
+                "'--only-matching' and '--format' cannot be used with \
+                 '--invert-match'")));
+        }
 
-        let case_sensitive = env::var("CASE_INSENSITIVE").is_err();
-        let search_fn: for<'r, 's> fn(&'r str, &'s str) -> Vec<&'s str>;
-        search_fn = if case_sensitive
-            { search } else { search_case_insensitive };
+        let replace = matches.value("replace").map(String::from);
+        let in_place = matches.flag("in-place");
+        if replace.is_some() {
+            if invert || output != OutputMode::Lines || show != Show::Line {
+                return Err(Error::Argument(String::from(
+                    "'--replace' cannot be used with '--invert-match' or \
+                     another output mode")));
+            }
+            if multiline {
+                return Err(Error::Argument(String::from(
+                    "'--replace' cannot be used with '--multiline'")));
+            }
+            if in_place && paths.iter().any(|p| p == "-") {
+                return Err(Error::Argument(String::from(
+                    "'--in-place' cannot write to standard input")));
+            }
+        } else if in_place {
+            return Err(Error::Argument(String::from(
+                "'--in-place' needs '--replace'")));
+        }
 
-        Ok(Config { query, filename, case_sensitive, search_fn })
+        let binary = match matches.last_of(&["binary-files", "text",
+                                             "skip-binary"]) {
+            Some("text") => Binary::Text,
+            Some("skip-binary") => Binary::Skip,
+            Some(_) => match matches.value("binary-files") {
+                Some("binary") => Binary::Report,
+                Some("text") => Binary::Text,
+                Some("without-match") => Binary::Skip,
+                value => return Err(Error::Argument(format!(
+                    "invalid value '{}' for '--binary-files': expected \
+                     binary, text or without-match", value.unwrap_or("")))),
+            },
+            None => Binary::Report,
+        };
+        let color = match matches.value("color") {
+            None | Some("auto") => Color::Auto,
+            Some("always") => Color::Always,
+            Some("never") => Color::Never,
+            Some(value) => return Err(Error::Argument(format!(
+                "invalid value '{}' for '--color': expected auto, always \
+                 or never", value))),
+        };
+        let verbose = matches.flag("verbose");
+        let mmap = !matches.flag("no-mmap");
+        let threads = match matches.number("threads")? {
+            Some(0) => return Err(Error::Argument(String::from(
+                "invalid value for '--threads': at least 1 is needed"))),
+            Some(n) => n,
+            None => thread::available_parallelism().map_or(1, |n| n.get()),
+        };
+        let sort = match matches.value("sort") {
+            None | Some("none") => Sort::None,
+            Some("path") => Sort::Path,
+            Some(value) => return Err(Error::Argument(format!(
+                "invalid value '{}' for '--sort': expected path or none",
+                value))),
+        };
+
+        Ok(Config { query, queries, paths, case, case_sensitive, mode, follow_links,
+                    max_depth, filter, line_number, column, byte_offset,
+                    before_context, after_context, invert, max_count, max_total,
+                    multiline, boundary, output, show,
+                    replace, in_place, binary, color,
+                    verbose, mmap, threads, sort, matcher })
     }
 }
 
-pub fn run(config: Config) -> Result<(), Box<Error>> {
-    let mut f = File::open(config.filename)?;
-    let mut contents = String::new();
-    f.read_to_string(&mut contents)?;
+/// Search every file under the configured paths.
+///
+/// With more than one file to search and more than one thread, files are
+/// searched at once on a pool of `Config::threads` threads, while a writer
+/// thread prints them one after another.  Otherwise each is searched in
+/// turn.  Either way results come out as soon as they are found, a file at
+/// a time.
+///
+/// Files that cannot be read are reported on stderr, skipped, and listed
+/// in the returned `Status`.  Failing to write the results ends the search,
+/// as does reaching `Config::max_total` matching lines.
+pub fn run(config: Config) -> Result<Status, Error> {
+    // Name the file on each result when more than one might be searched.
+    let with_filename = config.paths.len() > 1
+        || config.paths.iter().any(|p| Path::new(p).is_dir());
+    if config.threads == 1 || !with_filename {
+        search_in_turn(&config, with_filename)
+    } else {
+        search_at_once(config, with_filename)
+    }
+}
 
-    let results = (config.search_fn)(&config.query, &contents);
-        
-    for line in results {
-        println!("{}", line);
+/// The files under the configured paths, and `-` for standard input.
+fn inputs(config: &Config) -> Box<dyn Iterator<Item = Result<PathBuf, Error>> + '_> {
+    Box::new(config.paths.iter().flat_map(move |path| {
+        let files: Box<dyn Iterator<Item = _>> = if path == "-" {
+            Box::new(iter::once(Ok(PathBuf::from(path))))
+        } else {
+            Box::new(Walk::filtered(&[path], config.follow_links,
+                                    config.max_depth, config.filter.clone()))
+        };
+        files
+    }))
+}
+
+/// Search each input in turn, writing the results straight out.
+fn search_in_turn(config: &Config, with_filename: bool) -> Result<Status, Error> {
+    let budget = config.max_total.map(Budget::new);
+    let mut printer = Printer::new(config, printer::stdout(), with_filename);
+    let mut status = Status::default();
+    for input in inputs(config) {
+        if budget.as_ref().is_some_and(Budget::is_spent) {
+            break;
+        }
+        let result = input.and_then(|path| {
+            search_path(config, budget.as_ref(), &mut printer, &path)
+        });
+        if !note(config, &mut status, result)? {
+            break;
+        }
+    }
+    finish(printer, status)
+}
+
+/// Number of pieces of a file's output waiting to be printed before the
+/// thread searching it has to wait too.
+const PIECES: usize = 4;
+
+/// Search the inputs on a pool of threads, each file into a `Pipe` the
+/// writer thread prints from.
+fn search_at_once(config: Config, with_filename: bool) -> Result<Status, Error> {
+    let config = Arc::new(config);
+    // Set by the writer when there is no point searching any more.
+    let stop = Arc::new(AtomicBool::new(false));
+    // Shared by every file, so that all of them stop once it is spent.
+    let budget = Arc::new(config.max_total.map(Budget::new));
+    let (sender, receiver) = mpsc::channel();
+    let writer = {
+        let config = Arc::clone(&config);
+        let stop = Arc::clone(&stop);
+        let budget = Arc::clone(&budget);
+        thread::spawn(move || {
+            write_results(&config, budget.as_ref().as_ref(), with_filename,
+                          receiver, &stop)
+        })
+    };
+    let called_off = |stop: &AtomicBool, budget: Option<&Budget>| {
+        stop.load(Ordering::Relaxed) || budget.is_some_and(Budget::is_spent)
+    };
+
+    let mut pool = ThreadPool::new(config.threads);
+    for input in inputs(&config) {
+        if called_off(&stop, budget.as_ref().as_ref()) {
+            break;
+        }
+        let path = match input {
+            Ok(path) => path,
+            Err(e) => {
+                let _ = sender.send(Searched::Failed(e));
+                continue;
+            },
+        };
+        if path == Path::new("-") {
+            let _ = sender.send(Searched::Stdin);
+            continue;
+        }
+        // Files sorted by path are printed in the order they are found, so
+        // the writer is told of each now; otherwise it is told once the
+        // file has something to print.
+        let (pipe, pieces) = mpsc::sync_channel(PIECES);
+        let mut announce = if config.sort == Sort::Path {
+            let _ = sender.send(Searched::File(pieces));
+            None
+        } else {
+            Some((sender.clone(), pieces))
+        };
+        let config = Arc::clone(&config);
+        let stop = Arc::clone(&stop);
+        let budget = Arc::clone(&budget);
+        pool.execute(move || {
+            let budget = budget.as_ref().as_ref();
+            if called_off(&stop, budget) {
+                return;
+            }
+            let done = pipe.clone();
+            let hand_on = move |output| {
+                if let Some((sender, pieces)) = announce.take() {
+                    let _ = sender.send(Searched::File(pieces));
+                }
+                pipe.send(Piece::Output(output))
+                    .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))
+            };
+            let mut printer = Printer::new(&config, Pipe::new(hand_on), with_filename);
+            let result = search_path(&config, budget, &mut printer, &path);
+            if printer.close().is_ok() {
+                let _ = done.send(Piece::Done(result));
+            }
+        });
+    }
+    pool.wait();
+    drop(sender);
+    writer.join().unwrap()
+}
+
+/// What the writer is sent about each input.
+enum Searched {
+    /// A file being searched, and where its output comes from.
+    File(mpsc::Receiver<Piece>),
+    /// The writer is to search standard input itself.
+    Stdin,
+    Failed(Error),
+}
+
+/// What comes through a file's pipe.
+enum Piece {
+    Output(FileOutput),
+    /// The file has been searched, with this many matching lines.
+    Done(Result<usize, Error>),
+}
+
+/// Print what `receiver` is sent about each input, a file at a time, until
+/// there is nothing more to print.
+fn write_results(config: &Config, budget: Option<&Budget>, with_filename: bool,
+                 receiver: mpsc::Receiver<Searched>, stop: &AtomicBool)
+    -> Result<Status, Error> {
+    let mut printer = Printer::new(config, printer::stdout(), with_filename);
+    let mut status = Status::default();
+    for searched in receiver {
+        let result = match searched {
+            Searched::File(pieces) => append_file(&mut printer, pieces),
+            Searched::Stdin => {
+                search_path(config, budget, &mut printer, Path::new("-"))
+            },
+            Searched::Failed(e) => Err(e),
+        };
+        match note(config, &mut status, result) {
+            Ok(true) => {},
+            Ok(false) => {
+                stop.store(true, Ordering::Relaxed);
+                break;
+            },
+            Err(e) => {
+                stop.store(true, Ordering::Relaxed);
+                return Err(e);
+            },
+        }
+    }
+    finish(printer, status)
+}
+
+/// Print a file's output as it comes through its pipe.
+///
+/// Returns what searching the file came to.
+fn append_file(printer: &mut Printer, pieces: mpsc::Receiver<Piece>)
+    -> Result<usize, Error> {
+    for piece in pieces {
+        match piece {
+            Piece::Output(output) => printer.append(output).map_err(Error::Output)?,
+            Piece::Done(result) => return result,
+        }
     }
+    // The search was called off before the file was reached.
+    Ok(0)
+}
+
+/// Note in `status` what searching an input came to.
+///
+/// Returns whether to go on: not once the output has been closed, nor
+/// once something has matched in quiet mode.  Failing to write is an
+/// error; failing to read is reported and noted.
+fn note(config: &Config, status: &mut Status, result: Result<usize, Error>)
+    -> Result<bool, Error> {
+    match result {
+        Ok(count) => status.matched |= count > 0,
+        // The output has been closed, so there is no point going on.
+        Err(Error::Output(ref e)) if e.kind() == io::ErrorKind::BrokenPipe => {
+            return Ok(false);
+        },
+        Err(e @ Error::Output(_)) => return Err(e),
+        Err(e) => {
+            eprintln!("minigrep: {}", e);
+            status.errors.push(e);
+        },
+    }
+    // One match is enough to know the exit status.
+    Ok(!(status.matched && config.output == OutputMode::Quiet))
+}
+
+/// End the output, and with it the search.
+fn finish(mut printer: Printer, status: Status) -> Result<Status, Error> {
+    match printer.finish().and_then(|_| printer.flush()) {
+        Err(ref e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(status),
+        Err(e) => Err(Error::Output(e)),
+        Ok(()) => Ok(status),
+    }
+}
+
+/// Search the file at `path`, or standard input for `-`, sending the
+/// results to `printer`, or replace the matches in it.  Matching lines are
+/// taken from `budget`, if there is one, until it is spent.
+///
+/// Returns the number of matching lines.
+fn search_path<W: Out>(config: &Config, budget: Option<&Budget>,
+                         printer: &mut Printer<W>, path: &Path)
+    -> Result<usize, Error> {
+    if let Some(ref replacement) = config.replace {
+        return replace_file(config, printer, path, &Template::new(replacement));
+    }
+    let searcher = searcher(config, budget);
+    if path == Path::new("-") {
+        let stdin = io::stdin();
+        return search_input(config, &searcher, printer, Path::new(STDIN_NAME),
+                            stdin.lock());
+    }
+    let file = File::open(path).map_err(|e| Error::from_io(path, e))?;
+    let size = file.metadata().map_or(0, |m| m.len());
+    if config.mmap && mmap::should_map(size) {
+        // Should mapping fail, reading may still work.
+        if let Ok(map) = Mmap::open(&file) {
+            if utf16::bom(&map).is_some() {
+                return search_input(config, &searcher, printer, path, &map[..]);
+            }
+            return search_text(config, printer, path,
+                               |sink| searcher.search_slice(&map, sink));
+        }
+    }
+    let reader = io::BufReader::with_capacity(stream::CHUNK, file);
+    search_input(config, &searcher, printer, path, reader)
+}
+
+/// Set up a searcher that reads no more than the output mode needs.
+fn searcher<'c>(config: &'c Config, budget: Option<&'c Budget>) -> Searcher<'c> {
+    let mut searcher = Searcher::new(&*config.matcher);
+    searcher.invert = config.invert;
+    searcher.max_count = config.max_count;
+    searcher.budget = budget;
+    searcher.multiline = config.multiline;
+    searcher.binary = config.binary;
+    match config.output {
+        // Matches shown on their own have no lines around them, as with
+        // grep.
+        OutputMode::Lines if config.show != Show::Line => {},
+        OutputMode::Lines | OutputMode::Json => {
+            searcher.before = config.before_context;
+            searcher.after = config.after_context;
+        },
+        OutputMode::Count => {},
+        // The first match settles these.
+        OutputMode::FilesWithMatches | OutputMode::FilesWithoutMatch
+            | OutputMode::Quiet => {
+                searcher.max_count = Some(config.max_count.map_or(1, |max| max.min(1)));
+            },
+    }
+    // Binary files are only reported differently when lines would be shown.
+    if config.binary == Binary::Report
+        && !matches!(config.output, OutputMode::Lines | OutputMode::Json) {
+        searcher.binary = Binary::Text;
+    }
+    searcher
+}
 
-    Ok(())
+/// Search one input, sending the results to `printer`.
+///
+/// Returns the number of matching lines.
+fn search_input<R, W>(config: &Config, searcher: &Searcher,
+                      printer: &mut Printer<W>, path: &Path, mut reader: R)
+    -> Result<usize, Error>
+    where R: BufRead, W: Out
+{
+    let bom = match reader.fill_buf() {
+        Ok(buf) => utf16::bom(buf),
+        Err(e) => return Err(Error::from_io(path, e)),
+    };
+    if let Some(endian) = bom {
+        reader.consume(2);
+        let decoder = io::BufReader::new(utf16::Decoder::new(reader, endian));
+        return search_text(config, printer, path,
+                           |sink| searcher.search(decoder, sink));
+    }
+    search_text(config, printer, path, |sink| searcher.search(reader, sink))
 }
 
-fn search<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
-    contents.lines()
-        .filter(|line| line.contains(query))
-        .collect()
+/// Search one input of UTF-8 text, or something like it, with `search`,
+/// which passes what it finds to the sink it is given.
+fn search_text<W, F>(config: &Config, printer: &mut Printer<W>, path: &Path,
+                     search: F)
+    -> Result<usize, Error>
+    where W: Out, F: FnOnce(&mut dyn FnMut(Output) -> io::Result<()>)
+                             -> io::Result<usize>
+{
+    printer.begin(path).map_err(Error::Output)?;
+    // Tell failures to write the results from failures to read the input.
+    let mut write_failed = false;
+    let result = match config.output {
+        OutputMode::Lines | OutputMode::Json => {
+            search(&mut |output| {
+                let result = printer.output(path, output);
+                write_failed = result.is_err();
+                result
+            })
+        },
+        _ => search(&mut |_| Ok(())),
+    };
+    let count = match result {
+        Ok(count) => count,
+        Err(e) if write_failed => return Err(Error::Output(e)),
+        Err(e) => return Err(Error::from_io(path, e)),
+    };
+    printer.summary(path, count).map_err(Error::Output)?;
+    Ok(count)
 }
 
-fn search_case_insensitive<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
-    let query = &query.to_lowercase();
+/// Replace the matches in one file, showing the changes as a diff, or
+/// writing them to the file with `in_place`.
+///
+/// Binary files are skipped, and files with no matches left alone.
+/// Returns the number of matching lines.
+fn replace_file<W: Out>(config: &Config, printer: &mut Printer<W>,
+                          path: &Path, template: &Template)
+    -> Result<usize, Error> {
+    let mut bytes = Vec::new();
+    let (name, read) = if path == Path::new("-") {
+        (Path::new(STDIN_NAME), io::stdin().read_to_end(&mut bytes).map(|_| ()))
+    } else {
+        (path, File::open(path).and_then(|mut f| f.read_to_end(&mut bytes))
+                                .map(|_| ()))
+    };
+    read.map_err(|e| Error::from_io(name, e))?;
+    if bytes.contains(&0) {
+        return Ok(0);
+    }
+    let contents = String::from_utf8(bytes)
+        .map_err(|_| Error::Encoding { path: name.to_path_buf() })?;
 
-    contents.lines()
-        .filter(|line| line.to_lowercase().contains(query))
-        .collect()
+    let matches = matcher::search(&*config.matcher, contents.as_bytes());
+    let changes = replace::changes(&matches, &*config.matcher, template);
+    if changes.is_empty() {
+        return Ok(matches.len());
+    }
+    if config.in_place {
+        let new = replace::apply(&contents, &changes);
+        replace::write_atomically(path, new.as_bytes())
+            .map_err(|e| Error::from_io(path, e))?;
+    } else {
+        let diff = replace::diff(&name.to_string_lossy(), &contents, &changes);
+        printer.diff(&diff).map_err(Error::Output)?;
+    }
+    Ok(matches.len())
+}
+
+/// The lines of `contents` with `query` in them, each found only when it
+/// is asked for.  For input that is read, see `matcher::read_matches`.
+pub fn matches<'a>(query: &str, contents: &'a str) -> Matches<'a, Literal> {
+    matcher::matches(Literal::new(query), contents.as_bytes())
+}
+
+/// The lines of `contents` with `query` in them regardless of case, each
+/// found only when it is asked for.
+pub fn matches_case_insensitive<'a>(query: &str, contents: &'a str)
+    -> Matches<'a, CaseInsensitive> {
+    matcher::matches(CaseInsensitive::new(query), contents.as_bytes())
+}
+
+/// Search for `query`.
+pub fn search<'a>(query: &str, contents: &'a str) -> Vec<Match<'a>> {
+    matches(query, contents).collect()
+}
+
+/// Search for `query` regardless of case.
+pub fn search_case_insensitive<'a>(query: &str, contents: &'a str) -> Vec<Match<'a>> {
+    matches_case_insensitive(query, contents).collect()
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use matcher::Regexes;
+
+    fn search_regex<'a>(query: &str, contents: &'a str) -> Vec<Match<'a>> {
+        let queries: Vec<&str> = query.split('\n').collect();
+        matcher::search(&Regexes::new(&queries, false).unwrap(), contents.as_bytes())
+    }
 
-    static CONTENTS: &'static str = "\
+    fn lines(matches: Vec<Match>) -> Vec<String> {
+        matches.iter().map(|m| String::from_utf8_lossy(&m.line).into_owned()).collect()
+    }
+
+    static CONTENTS: &str = "\
 Rust:
 safe, fast, productive.
 Pick three.
@@ -77,7 +921,7 @@ It's all duct tape and baloney.";
         
         assert_eq!(
             vec!["safe, fast, productive."],
-            search(query, CONTENTS));
+            lines(search(query, CONTENTS)));
     }
 
     #[test]
@@ -86,7 +930,7 @@ It's all duct tape and baloney.";
 
         assert_eq!(
             vec!["Pick three."],
-            search(query, CONTENTS));
+            lines(search(query, CONTENTS)));
     }
 
     #[test]
@@ -95,7 +939,165 @@ It's all duct tape and baloney.";
 
         assert_eq!(
             vec!["Rust:", "Trust me."],
-            search_case_insensitive(query, CONTENTS));
+            lines(search_case_insensitive(query, CONTENTS)));
+    }
+
+    #[test]
+    fn matches_compose() {
+        let mut found = matches_case_insensitive("rust", CONTENTS);
+        assert_eq!(Some(1), found.next().map(|m| m.line_number));
+        assert_eq!(vec!["Trust me."], lines(found.collect()));
+        assert_eq!(vec!["Pick three."],
+                   lines(matches("e", CONTENTS).filter(|m| m.start == 8).take(1)
+                                               .collect()));
+    }
+
+    #[test]
+    fn match_positions() {
+        let matches = search_case_insensitive("RUST", CONTENTS);
+        assert_eq!(2, matches.len());
+        assert_eq!(
+            Match { line_number: 4, offset: 42, start: 1, end: 5, pattern: 0,
+                    distance: 0, spans: vec![(1, 5)], line: b"Trust me."[..].into() },
+            matches[1]);
+        assert_eq!(2, matches[1].column());
+    }
+
+    #[test]
+    fn case_insensitive_span_is_in_original_line() {
+        let matches = search_case_insensitive("straße", "Die STRAßE hier");
+        assert_eq!((4, 11), (matches[0].start, matches[0].end));
+    }
+
+    #[test]
+    fn case_insensitive_full_folding() {
+        let matches = search_case_insensitive("STRASSE", "Die Straße\nstrasse");
+        assert_eq!(vec![(4, 11)], matches[0].spans);
+        assert_eq!(vec![(0, 7)], matches[1].spans);
+    }
+
+    #[test]
+    fn smart_case() {
+        assert!(!Case::Smart.is_sensitive("rust", Mode::Literal));
+        assert!(Case::Smart.is_sensitive("Rust", Mode::Literal));
+        assert!(!Case::Smart.is_sensitive(r"\Srust", Mode::Regex));
+        assert!(Case::Smart.is_sensitive(r"\Srust", Mode::Literal));
+    }
+
+    #[test]
+    fn several_patterns() {
+        let queries = ["three", "duct", "fast"].map(String::from);
+        let matcher = matcher::new(&queries, Mode::Literal, Case::Sensitive, false,
+                                   &Boundary::Any)
+            .unwrap();
+        let matches = matcher::search(&*matcher, CONTENTS.as_bytes());
+        assert_eq!(vec![2, 3, 5], matches.iter().map(|m| m.line_number)
+                                         .collect::<Vec<_>>());
+        assert_eq!(vec![2, 0, 1], matches.iter().map(|m| m.pattern)
+                                         .collect::<Vec<_>>());
+        let queries = ["TRUST", "me"].map(String::from);
+        let matcher = matcher::new(&queries, Mode::Literal, Case::Insensitive, false,
+                                   &Boundary::Any)
+            .unwrap();
+        let matches = matcher::search(&*matcher, CONTENTS.as_bytes());
+        assert_eq!(vec![(0, 5), (6, 8)], matches[0].spans);
+        let matches = search_regex("p\\w+\nf\\w+", CONTENTS);
+        assert_eq!((1, vec![(2, 4), (6, 10), (12, 22)]),
+                   (matches[0].pattern, matches[0].spans.clone()));
+    }
+
+    #[test]
+    fn fuzzy() {
+        let search = |query: &str, case| {
+            let queries = [query.to_string()];
+            let matcher = matcher::new(&queries, Mode::Fuzzy(1), case,
+                                       false, &Boundary::Any).unwrap();
+            matcher::search(&*matcher, CONTENTS.as_bytes())
+        };
+        // "productive" and "duct" are each one edit away.
+        let matches = search("duck", Case::Sensitive);
+        assert_eq!(vec![2, 5], matches.iter().map(|m| m.line_number)
+                                      .collect::<Vec<_>>());
+        assert_eq!((1, (9, 12)), (matches[1].distance,
+                                  (matches[1].start, matches[1].end)));
+        assert_eq!(vec!["Pick three."], lines(search("PICKT", Case::Insensitive)));
+    }
+
+    #[test]
+    fn boundaries() {
+        let search = |query: &str, mode, boundary, contents: &'static str| {
+            let queries = [query.to_string()];
+            let matcher = matcher::new(&queries, mode, Case::Insensitive, false,
+                                       &boundary)
+                .unwrap();
+            matcher::search(&*matcher, contents.as_bytes())
+        };
+        let spans = |boundary| {
+            search("rust", Mode::Literal, boundary, "Trust rust, rusty")
+                .into_iter().flat_map(|m| m.spans).collect::<Vec<_>>()
+        };
+        assert_eq!(vec![(6, 10)], spans(Boundary::Word));
+        assert!(spans(Boundary::Line).is_empty());
+        assert_eq!(vec![(1, 5), (6, 10)], spans(Boundary::Chars(String::from("T ,"))));
+        // A match without the boundary does not hide others that have it.
+        assert_eq!(vec!["ab"], lines(search("a|ab", Mode::Regex, Boundary::Line,
+                                            "ab\nabc")));
+        let matches = search("rust", Mode::Fuzzy(1), Boundary::Word,
+                             "trust rust\nrustiest");
+        assert_eq!(vec!["trust rust"], lines(matches.clone()));
+        assert_eq!(vec![(6, 10)], matches[0].spans);
+        assert!(!Boundary::Word.allows("über_x".as_bytes(), 0, 5));
+        assert!(Boundary::Word.allows("(über)".as_bytes(), 1, 6));
+        assert!(Boundary::Word.allows(b"\xFFab", 1, 3));
+        assert!(Boundary::Line.allows(b"a\nb c\r\nd", 2, 5));
+        assert!(!Boundary::Line.allows(b"a\nb c\r\nd", 2, 3));
+    }
+
+    #[test]
+    fn every_span_in_the_line() {
+        let matches = search_case_insensitive("a", "Banana\nnone\nA");
+        assert_eq!(vec![(1, 2), (3, 4), (5, 6)], matches[0].spans);
+        assert_eq!(vec![(0, 1)], matches[1].spans);
+        let matches = search_regex("x*", "ab");
+        assert_eq!(vec![(0, 0), (1, 1), (2, 2)], matches[0].spans);
+    }
+
+    #[test]
+    fn exit_codes() {
+        let status = |matched, errors| Status {
+            matched,
+            errors: if errors { vec![Error::Argument(String::new())] }
+                    else { Vec::new() },
+        };
+        assert_eq!(0, status(true, false).exit_code(false));
+        assert_eq!(1, status(false, false).exit_code(false));
+        assert_eq!(2, status(false, true).exit_code(false));
+        assert_eq!(2, status(true, true).exit_code(false));
+        assert_eq!(0, status(true, true).exit_code(true));
+        assert_eq!(2, status(false, true).exit_code(true));
+    }
+
+    #[test]
+    fn regex() {
+        assert_eq!(
+            vec!["Pick three.", "It's all duct tape and baloney."],
+            lines(search_regex(r"^\w+'?\w* (three|all)", CONTENTS)));
+    }
+
+    #[test]
+    fn regex_case_insensitive() {
+        assert_eq!(
+            vec!["Rust:"],
+            lines(matcher::search(&Regexes::new(&["^rust"], true).unwrap(),
+                                  CONTENTS.as_bytes())));
+    }
+
+    #[test]
+    fn invalid_regex_is_an_error() {
+        let queries = [String::from("(fast")];
+        assert!(matcher::new(&queries, Mode::Regex, Case::Sensitive, false,
+                             &Boundary::Any)
+                .is_err());
     }
 }
 
@@ -2,20 +2,39 @@ extern crate minigrep;
 
 use std::env;
 use std::process;
-use minigrep::Config;
+use minigrep::args::{self, Command};
+use minigrep::OutputMode;
 
 fn main() {
-    let config = Config::new(env::args()).unwrap_or_else(|err| {
-        println!("Problem parsing arguments: {}", err);
-        process::exit(1);
-    });
+    let config = match args::parse(env::args()) {
+        Ok(Command::Search(config)) => *config,
+        Ok(Command::Help) => {
+            print!("{}", args::usage());
+            process::exit(0);
+        },
+        Ok(Command::Version) => {
+            println!("{}", args::version());
+            process::exit(0);
+        },
+        Err(err) => {
+            eprintln!("Problem parsing arguments: {}", err);
+            eprintln!("Try 'minigrep --help' for more information.");
+            process::exit(2);
+        },
+    };
 
-    println!("Searching for {}", config.query);
-    println!("In file {}", config.filename);
+    if config.verbose {
+        eprintln!("Searching for {}", config.query);
+        eprintln!("In {}", config.paths.join(", "));
+    }
 
-    if let Err(e) = minigrep::run(config) {
-        println!("Application error: {}", e);
-        process::exit(1);
+    let quiet = config.output == OutputMode::Quiet;
+    match minigrep::run(config) {
+        Ok(status) => process::exit(status.exit_code(quiet)),
+        Err(e) => {
+            eprintln!("Application error: {}", e);
+            process::exit(2);
+        },
     }
 }
 
@@ -0,0 +1,743 @@
+//! What a search looks for, behind one trait.
+//!
+//! A `Matcher` finds matches in a line of input, given as bytes.  The
+//! searchers drive any matcher a line at a time, so a new kind of search
+//! only has to say where its matches are.  A matcher that can find where a
+//! match might be in many lines at once says so with `candidate`, and then
+//! only the lines around the candidates are split out and searched.  In
+//! multiline mode the whole input is the haystack instead, and
+//! `touched_lines` splits what is found back into lines.
+//!
+//! Input need not be UTF-8.  Matchers of plain text find matches within
+//! the stretches of a line that are valid, and regular expressions step
+//! over an invalid byte as a character that only `.` and negated classes
+//! match.  Positions are always byte offsets into the input as it is.
+
+use std::borrow::Cow;
+use std::io;
+use std::io::prelude::*;
+use std::str;
+
+use aho_corasick::AhoCorasick;
+use fold;
+use fuzzy::Fuzzy;
+use memchr::{self, Finder};
+use regex::{Captures, Regex};
+use {Boundary, Case, Error, Match, Mode};
+
+/// A match found by a `Matcher`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+    /// Byte range of the match in the haystack.
+    pub start: usize,
+    pub end: usize,
+    /// Index of the pattern that matched, for matchers with several.
+    pub pattern: usize,
+    /// Edits needed for the match, for approximate matchers.
+    pub distance: usize,
+}
+
+impl Span {
+    /// A match of the first pattern, with no edits.
+    pub fn new(start: usize, end: usize) -> Span {
+        Span { start, end, pattern: 0, distance: 0 }
+    }
+}
+
+/// Finds matches in a line.
+pub trait Matcher {
+    /// Find the leftmost match in `haystack` starting at or after byte
+    /// `at`.  Anything before `at` may still be looked at, as anchors do.
+    fn find_at(&self, haystack: &[u8], at: usize) -> Option<Span>;
+
+    /// Capture groups of the match `find_at` reports, for matchers that
+    /// have them.  Group 0 is the whole match.
+    fn captures_at(&self, _haystack: &[u8], _at: usize) -> Option<Captures> {
+        None
+    }
+
+    /// Where in `buffer`, which holds many lines, the first match at or
+    /// after `at` might be.  Lines before that are not searched, so the
+    /// candidate must be on the match's line or earlier.
+    ///
+    /// By default every line is a candidate.
+    fn candidate(&self, buffer: &[u8], at: usize) -> Option<usize> {
+        if at < buffer.len() { Some(at) } else { None }
+    }
+
+    /// Every match in `haystack`, in order and not overlapping.
+    ///
+    /// After an empty match the search goes on a character further along,
+    /// so it is not found again.
+    fn find_all(&self, haystack: &[u8]) -> Vec<Span> {
+        let mut spans = Vec::new();
+        let mut at = 0;
+        while at <= haystack.len() {
+            let span = match self.find_at(haystack, at) {
+                Some(span) => span,
+                None => break,
+            };
+            spans.push(span);
+            at = if span.end > span.start {
+                span.end
+            } else {
+                span.end + text(&haystack[span.end..]).chars().next()
+                    .map_or(1, |c| c.len_utf8())
+            };
+        }
+        spans
+    }
+}
+
+/// A borrowed matcher finds what it does.
+impl<M: Matcher + ?Sized> Matcher for &M {
+    fn find_at(&self, haystack: &[u8], at: usize) -> Option<Span> {
+        (**self).find_at(haystack, at)
+    }
+
+    fn captures_at(&self, haystack: &[u8], at: usize) -> Option<Captures> {
+        (**self).captures_at(haystack, at)
+    }
+
+    fn candidate(&self, buffer: &[u8], at: usize) -> Option<usize> {
+        (**self).candidate(buffer, at)
+    }
+
+    fn find_all(&self, haystack: &[u8]) -> Vec<Span> {
+        (**self).find_all(haystack)
+    }
+}
+
+/// The matcher for `queries` searched in `mode`, with letter case mattering
+/// as `case` says for all of them together.  With `multi_line`, regular
+/// expressions are made to search many lines at once.  Only matches with
+/// `boundary` around them are found.
+///
+/// Fails if a query is not a valid regular expression in regex mode.
+pub fn new(queries: &[String], mode: Mode, case: Case, multi_line: bool,
+           boundary: &Boundary)
+    -> Result<Box<dyn Matcher + Send + Sync>, Error> {
+    let case_insensitive = !case.is_sensitive(&queries.join("\n"), mode);
+    Ok(match (mode, queries) {
+        (Mode::Literal, [query]) if case_insensitive => {
+            bounded(CaseInsensitive::new(query), boundary)
+        },
+        (Mode::Literal, [query]) => bounded(Literal::new(query), boundary),
+        (Mode::Literal, _) => bounded(Patterns::new(queries, case_insensitive), boundary),
+        (Mode::Regex, _) => {
+            let mut regexes = if multi_line {
+                Regexes::multi_line(queries, case_insensitive)?
+            } else {
+                Regexes::new(queries, case_insensitive)?
+            };
+            regexes.boundary = boundary.clone();
+            Box::new(regexes)
+        },
+        (Mode::Fuzzy(k), _) => {
+            bounded(Approximate::new(queries, k, case_insensitive), boundary)
+        },
+    })
+}
+
+fn bounded<M>(matcher: M, boundary: &Boundary) -> Box<dyn Matcher + Send + Sync>
+    where M: Matcher + Send + Sync + 'static
+{
+    match *boundary {
+        Boundary::Any => Box::new(matcher),
+        _ => Box::new(Bounded { matcher, boundary: boundary.clone() }),
+    }
+}
+
+/// Matches of another matcher that have a `Boundary` around them.
+///
+/// When a match does not, the search goes on from a character after where
+/// it starts, so a later match that overlaps it is still found.
+pub struct Bounded<M> {
+    pub matcher: M,
+    pub boundary: Boundary,
+}
+
+impl<M: Matcher> Matcher for Bounded<M> {
+    fn find_at(&self, haystack: &[u8], mut at: usize) -> Option<Span> {
+        loop {
+            let span = self.matcher.find_at(haystack, at)?;
+            if self.boundary.allows(haystack, span.start, span.end) {
+                return Some(span);
+            }
+            at = span.start + text(&haystack[span.start..]).chars().next()
+                .map_or(1, |c| c.len_utf8());
+            if at > haystack.len() {
+                return None;
+            }
+        }
+    }
+
+    fn captures_at(&self, haystack: &[u8], at: usize) -> Option<Captures> {
+        let span = self.find_at(haystack, at)?;
+        self.matcher.captures_at(haystack, span.start)
+            .filter(|caps| caps.get(0) == Some((span.start, span.end)))
+    }
+
+    fn candidate(&self, buffer: &[u8], at: usize) -> Option<usize> {
+        self.matcher.candidate(buffer, at)
+    }
+}
+
+/// Every line of `contents` that `matcher` matches, with its matches.
+///
+/// Lines are split as `str::lines` splits them, but only around the
+/// matcher's candidates.
+pub fn search<'a, M: Matcher + ?Sized>(matcher: &M, contents: &'a [u8])
+    -> Vec<Match<'a>> {
+    matches(matcher, contents).collect()
+}
+
+/// The lines of `contents` that `matcher` matches, as `search` finds them,
+/// but each only when it is asked for.  Once the caller has seen enough
+/// it can stop, and the rest of `contents` is never searched.
+///
+/// `matcher` may be borrowed, as `&Literal::new("x")`, or given outright.
+pub fn matches<M: Matcher>(matcher: M, contents: &[u8]) -> Matches<'_, M> {
+    Matches { matcher, contents, at: 0, line_number: 1, done: contents.is_empty() }
+}
+
+/// Iterator over the matching lines of a text; see `matches`.
+pub struct Matches<'a, M> {
+    matcher: M,
+    contents: &'a [u8],
+    /// The start of the next line to search, and its number.
+    at: usize,
+    line_number: usize,
+    done: bool,
+}
+
+impl<'a, M> Matches<'a, M> {
+    /// Where the lines not yet searched start, and the number of the first.
+    /// The lines before it have been counted, so once every match is found
+    /// only those after it are left to count.
+    pub fn position(&self) -> (usize, usize) {
+        (self.at, self.line_number)
+    }
+}
+
+impl<'a, M: Matcher> Iterator for Matches<'a, M> {
+    type Item = Match<'a>;
+
+    fn next(&mut self) -> Option<Match<'a>> {
+        let bytes = self.contents;
+        while !self.done {
+            let candidate = match self.matcher.candidate(bytes, self.at) {
+                Some(candidate) => candidate,
+                None => {
+                    self.done = true;
+                    break;
+                },
+            };
+            let at = self.at;
+            let start = memchr::memrchr(b'\n', &bytes[at..candidate])
+                .map_or(at, |i| at + i + 1);
+            let (end, next) = match memchr::memchr(b'\n', &bytes[candidate..]) {
+                Some(i) => (candidate + i, candidate + i + 1),
+                None => (bytes.len(), bytes.len()),
+            };
+            let line_number = self.line_number + memchr::count(b'\n', &bytes[at..start]);
+            let line = match bytes[start..end].strip_suffix(b"\r") {
+                Some(line) if next > end => line,
+                _ => &bytes[start..end],
+            };
+            self.at = next;
+            self.line_number = line_number + 1;
+            self.done = next == bytes.len();
+
+            let spans = self.matcher.find_all(line);
+            if let Some(first) = spans.first() {
+                return Some(Match {
+                    line_number,
+                    offset: start,
+                    start: first.start,
+                    end: first.end,
+                    pattern: first.pattern,
+                    distance: spans.iter().map(|m| m.distance).min().unwrap_or(0),
+                    spans: spans.iter().map(|m| (m.start, m.end)).collect(),
+                    line: Cow::Borrowed(line),
+                });
+            }
+        }
+        None
+    }
+}
+
+/// The lines read from `reader` that `matcher` matches, each read only
+/// when it is asked for.
+///
+/// Lines are split as `BufRead::lines` splits them.
+pub fn read_matches<M: Matcher, R: BufRead>(matcher: M, reader: R)
+    -> ReadMatches<M, R> {
+    ReadMatches { matcher, reader, buffer: Vec::new(), line_number: 0, offset: 0,
+                  done: false }
+}
+
+/// Iterator over the matching lines of a reader; see `read_matches`.
+///
+/// The lines are owned, as the reader's buffer is reused.  After an error
+/// reading there are no more.
+pub struct ReadMatches<M, R> {
+    matcher: M,
+    reader: R,
+    buffer: Vec<u8>,
+    /// Number of the last line read, and the offset of the next.
+    line_number: usize,
+    offset: usize,
+    done: bool,
+}
+
+impl<M: Matcher, R: BufRead> Iterator for ReadMatches<M, R> {
+    type Item = io::Result<Match<'static>>;
+
+    fn next(&mut self) -> Option<io::Result<Match<'static>>> {
+        while !self.done {
+            self.buffer.clear();
+            let read = match self.reader.read_until(b'\n', &mut self.buffer) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                },
+            };
+            let offset = self.offset;
+            self.offset += read;
+            self.line_number += 1;
+
+            let mut line = &self.buffer[..];
+            if let Some(rest) = line.strip_suffix(b"\n") {
+                line = rest.strip_suffix(b"\r").unwrap_or(rest);
+            }
+            let spans = self.matcher.find_all(line);
+            if let Some(first) = spans.first() {
+                return Some(Ok(Match {
+                    line_number: self.line_number,
+                    offset,
+                    start: first.start,
+                    end: first.end,
+                    pattern: first.pattern,
+                    distance: spans.iter().map(|m| m.distance).min().unwrap_or(0),
+                    spans: spans.iter().map(|m| (m.start, m.end)).collect(),
+                    line: Cow::Owned(line.to_vec()),
+                }));
+            }
+        }
+        self.done = true;
+        None
+    }
+}
+
+/// The lines of `contents` that `spans`, found in the whole of it, touch,
+/// each with the part of every span on it.
+///
+/// A span that takes in a line's terminator does not touch the next line
+/// unless it goes on into it.
+pub fn touched_lines<'a, I>(contents: &'a [u8], spans: I) -> Vec<Match<'a>>
+    where I: IntoIterator<Item = Span>
+{
+    let bytes = contents;
+    let mut found: Vec<Match> = Vec::new();
+    // The start of a line no later than the next span, and its number.
+    let mut at = 0;
+    let mut line_number = 1;
+    for span in spans {
+        // After the last terminator there is no line to touch.
+        if span.start == bytes.len() && (bytes.is_empty() || bytes.ends_with(b"\n")) {
+            break;
+        }
+        let mut start = memchr::memrchr(b'\n', &bytes[at..span.start])
+            .map_or(at, |i| at + i + 1);
+        line_number += memchr::count(b'\n', &bytes[at..start]);
+        loop {
+            let (end, next) = match memchr::memchr(b'\n', &bytes[start..]) {
+                Some(i) => (start + i, start + i + 1),
+                None => (bytes.len(), bytes.len()),
+            };
+            let line = match bytes[start..end].strip_suffix(b"\r") {
+                Some(line) if next > end => line,
+                _ => &bytes[start..end],
+            };
+            let part = (span.start.max(start) - start,
+                        (span.end - start).min(line.len()));
+            let part = (part.0.min(part.1), part.1);
+            match found.last_mut() {
+                Some(m) if m.line_number == line_number => {
+                    m.spans.push(part);
+                    m.distance = m.distance.min(span.distance);
+                },
+                _ => found.push(Match {
+                    line_number,
+                    offset: start,
+                    start: part.0,
+                    end: part.1,
+                    pattern: span.pattern,
+                    distance: span.distance,
+                    spans: vec![part],
+                    line: Cow::Borrowed(line),
+                }),
+            }
+            if span.end <= next || next == bytes.len() {
+                break;
+            }
+            start = next;
+            line_number += 1;
+        }
+        at = start;
+    }
+    found
+}
+
+/// The longest prefix of `haystack` that is valid UTF-8.
+fn text(haystack: &[u8]) -> &str {
+    match str::from_utf8(haystack) {
+        Ok(text) => text,
+        Err(e) => str::from_utf8(&haystack[..e.valid_up_to()]).unwrap_or(""),
+    }
+}
+
+/// The stretches of `haystack` that are valid UTF-8 and do not end before
+/// byte `at`, for matchers of text to search one after another.  Each
+/// comes with where it starts in `haystack` and where in it to search
+/// from.
+fn texts(haystack: &[u8], at: usize) -> impl Iterator<Item = (usize, &str, usize)> {
+    Texts { haystack, start: 0, done: false }
+        .filter(move |&(start, text)| start + text.len() >= at)
+        .map(move |(start, text)| (start, text, at.saturating_sub(start)))
+        .filter(|&(_, text, from)| text.is_char_boundary(from))
+}
+
+/// Iterator over the valid stretches of a haystack; see `texts`.  The
+/// last is empty if the haystack is, or ends with an invalid byte.
+struct Texts<'a> {
+    haystack: &'a [u8],
+    start: usize,
+    done: bool,
+}
+
+impl<'a> Iterator for Texts<'a> {
+    type Item = (usize, &'a str);
+
+    fn next(&mut self) -> Option<(usize, &'a str)> {
+        if self.done {
+            return None;
+        }
+        let start = self.start;
+        let rest = &self.haystack[start..];
+        match str::from_utf8(rest) {
+            Ok(text) => {
+                self.done = true;
+                Some((start, text))
+            },
+            Err(e) => {
+                let valid = e.valid_up_to();
+                let invalid = e.error_len().unwrap_or(rest.len() - valid);
+                self.start += valid + invalid;
+                Some((start, str::from_utf8(&rest[..valid]).unwrap_or("")))
+            },
+        }
+    }
+}
+
+/// A plain string, matched exactly.
+///
+/// Its occurrences are candidates, so buffers are searched a match at a
+/// time rather than a line at a time.
+pub struct Literal {
+    finder: Finder,
+}
+
+impl Literal {
+    pub fn new(query: &str) -> Literal {
+        Literal { finder: Finder::new(query.as_bytes()) }
+    }
+}
+
+impl Matcher for Literal {
+    fn find_at(&self, haystack: &[u8], at: usize) -> Option<Span> {
+        let start = at + self.finder.find(haystack.get(at..)?)?;
+        Some(Span::new(start, start + self.finder.needle().len()))
+    }
+
+    fn candidate(&self, buffer: &[u8], at: usize) -> Option<usize> {
+        if self.finder.needle().is_empty() {
+            return if at < buffer.len() { Some(at) } else { None };
+        }
+        self.finder.find(buffer.get(at..)?).map(|i| at + i)
+    }
+}
+
+/// A plain string, matched regardless of case by full case folding.
+pub struct CaseInsensitive {
+    query: Vec<char>,
+}
+
+impl CaseInsensitive {
+    pub fn new(query: &str) -> CaseInsensitive {
+        CaseInsensitive { query: fold::fold_str(query) }
+    }
+}
+
+impl Matcher for CaseInsensitive {
+    fn find_at(&self, haystack: &[u8], at: usize) -> Option<Span> {
+        texts(haystack, at).find_map(|(offset, text, from)| {
+            fold::find(&self.query, text, from)
+                .map(|(start, end)| Span::new(offset + start, offset + end))
+        })
+    }
+}
+
+/// Several plain strings, all looked for at once.
+pub struct Patterns {
+    automaton: AhoCorasick,
+}
+
+impl Patterns {
+    pub fn new<S: AsRef<str>>(patterns: &[S], case_insensitive: bool) -> Patterns {
+        Patterns { automaton: AhoCorasick::new(patterns, case_insensitive) }
+    }
+}
+
+impl Matcher for Patterns {
+    fn find_at(&self, haystack: &[u8], at: usize) -> Option<Span> {
+        texts(haystack, at).find_map(|(offset, text, from)| {
+            self.automaton.find_at(text, from).map(|m| Span {
+                start: offset + m.start,
+                end: offset + m.end,
+                pattern: m.pattern,
+                distance: 0,
+            })
+        })
+    }
+
+    fn find_all(&self, haystack: &[u8]) -> Vec<Span> {
+        texts(haystack, 0)
+            .flat_map(|(offset, text, _)| {
+                self.automaton.find_all(text).into_iter()
+                    .map(move |m| Span { start: offset + m.start, end: offset + m.end,
+                                         pattern: m.pattern, distance: 0 })
+            })
+            .collect()
+    }
+}
+
+/// Regular expressions; the leftmost match of any wins, the first
+/// pattern on a tie.
+pub struct Regexes {
+    regexes: Vec<Regex>,
+    /// What must surround a match; `Boundary::Any` for these constructors.
+    boundary: Boundary,
+}
+
+impl Regexes {
+    pub fn new<S: AsRef<str>>(patterns: &[S], case_insensitive: bool)
+        -> Result<Regexes, Error> {
+        let regexes = patterns.iter()
+            .map(|p| if case_insensitive {
+                Regex::new_case_insensitive(p.as_ref())
+            } else {
+                Regex::new(p.as_ref())
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Regexes { regexes, boundary: Boundary::Any })
+    }
+
+    /// Regular expressions for text of many lines, where `^` and `$`
+    /// match at the ends of each line.
+    pub fn multi_line<S: AsRef<str>>(patterns: &[S], case_insensitive: bool)
+        -> Result<Regexes, Error> {
+        let regexes = patterns.iter()
+            .map(|p| Regex::new_multi_line(p.as_ref(), case_insensitive))
+            .collect::<Result<_, _>>()?;
+        Ok(Regexes { regexes, boundary: Boundary::Any })
+    }
+
+    /// The leftmost match of `re` with the boundary around it.  The boundary
+    /// is checked as the regular expression runs, so it sees every way
+    /// `re` could match and not only the one it prefers.
+    fn captures(&self, re: &Regex, haystack: &[u8], at: usize) -> Option<Captures> {
+        match self.boundary {
+            Boundary::Any => re.captures_at(haystack, at),
+            ref boundary => re.captures_where(
+                haystack, at,
+                &|start| boundary.starts(haystack, start),
+                &|end| boundary.ends(haystack, end)),
+        }
+    }
+}
+
+impl Matcher for Regexes {
+    fn find_at(&self, haystack: &[u8], at: usize) -> Option<Span> {
+        self.regexes.iter().enumerate()
+            .filter_map(|(pattern, re)| {
+                self.captures(re, haystack, at).and_then(|caps| caps.get(0))
+                    .map(|(start, end)| Span { start, end, pattern, distance: 0 })
+            })
+            .min_by_key(|span| (span.start, span.pattern))
+    }
+
+    fn captures_at(&self, haystack: &[u8], at: usize) -> Option<Captures> {
+        let span = self.find_at(haystack, at)?;
+        self.captures(&self.regexes[span.pattern], haystack, at)
+    }
+}
+
+/// Plain strings found within a number of edits.
+///
+/// Matches are found from left to right, each with the fewest edits it
+/// can have where it starts.
+pub struct Approximate {
+    queries: Vec<Fuzzy>,
+}
+
+impl Approximate {
+    pub fn new<S: AsRef<str>>(queries: &[S], edits: usize, case_insensitive: bool)
+        -> Approximate {
+        let queries = queries.iter()
+            .map(|q| Fuzzy::new(q.as_ref(), edits, case_insensitive))
+            .collect();
+        Approximate { queries }
+    }
+}
+
+impl Matcher for Approximate {
+    fn find_at(&self, haystack: &[u8], at: usize) -> Option<Span> {
+        texts(haystack, at).find_map(|(offset, text, from)| {
+            let text = &text[from..];
+            self.queries.iter().enumerate()
+                .filter_map(|(pattern, f)| f.find(text).map(|m| Span {
+                    start: offset + from + m.start,
+                    end: offset + from + m.end,
+                    pattern,
+                    distance: m.distance,
+                }))
+                .min_by_key(|span| (span.start, span.distance, span.pattern))
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::Cell;
+
+    /// Finds one byte.
+    struct Letter(u8);
+
+    impl Matcher for Letter {
+        fn find_at(&self, haystack: &[u8], at: usize) -> Option<Span> {
+            let i = at + haystack.get(at..)?.iter().position(|&b| b == self.0)?;
+            Some(Span::new(i, i + 1))
+        }
+    }
+
+    #[test]
+    fn custom_matchers() {
+        let matches = search(&Letter(b'x'), b"axbx\nno\nx");
+        assert_eq!(vec![(1, vec![(1, 2), (3, 4)]), (3, vec![(0, 1)])],
+                   matches.into_iter().map(|m| (m.line_number, m.spans))
+                          .collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn touched_lines_of_spans() {
+        let contents = b"one\r\nBEGIN two\nthree END\nfour\n";
+        let re = Regexes::multi_line(&[r"(?s)BEGIN.*?END", r"four\n"], false).unwrap();
+        let matches = touched_lines(contents, re.find_all(contents));
+        assert_eq!(vec![(2, &b"BEGIN two"[..], vec![(0, 9)]),
+                        (3, &b"three END"[..], vec![(0, 9)]),
+                        (4, &b"four"[..], vec![(0, 4)])],
+                   matches.iter().map(|m| (m.line_number, &*m.line, m.spans.clone()))
+                          .collect::<Vec<_>>());
+        assert_eq!((15, 1), (matches[1].offset, matches[2].pattern));
+        let ends = touched_lines(b"a\n", vec![Span::new(2, 2)]);
+        assert!(ends.is_empty());
+    }
+
+    #[test]
+    fn matches_are_found_as_needed() {
+        /// Counts the lines it is asked to search.
+        struct Counted<'c>(Letter, &'c Cell<usize>);
+
+        impl<'c> Matcher for Counted<'c> {
+            fn find_at(&self, haystack: &[u8], at: usize) -> Option<Span> {
+                if at == 0 {
+                    self.1.set(self.1.get() + 1);
+                }
+                self.0.find_at(haystack, at)
+            }
+        }
+
+        let searched = Cell::new(0);
+        let matcher = Counted(Letter(b'x'), &searched);
+        let mut found = matches(&matcher, b"x\nno\nx\nx\n");
+        assert_eq!(Some(1), found.next().map(|m| m.line_number));
+        assert_eq!(1, searched.get());
+        assert_eq!(vec![3, 4], found.map(|m| m.line_number).collect::<Vec<_>>());
+        assert_eq!(4, searched.get());
+    }
+
+    #[test]
+    fn reads_matches() {
+        let input = &b"no\r\nx\xFFx\r\nno\nx"[..];
+        let found: Vec<_> = read_matches(Letter(b'x'), input)
+            .map(|m| m.unwrap())
+            .map(|m| (m.line_number, m.offset, m.line.into_owned(), m.spans))
+            .collect();
+        assert_eq!(vec![(2, 4, b"x\xFFx".to_vec(), vec![(0, 1), (2, 3)]),
+                        (4, 12, b"x".to_vec(), vec![(0, 1)])], found);
+        let mut first = read_matches(Letter(b'x'), input).take(1);
+        assert_eq!(Some(2), first.next().map(|m| m.unwrap().line_number));
+    }
+
+    #[test]
+    fn find_at_starts_later() {
+        assert_eq!(Some(Span::new(6, 8)), Literal::new("ab").find_at(b"ab ab ab", 4));
+        assert_eq!(Some(Span::new(3, 5)),
+                   CaseInsensitive::new("ab").find_at(b"AB AB", 1));
+        let span = Patterns::new(&["x", "b"], false).find_at(b"ab xb", 2).unwrap();
+        assert_eq!((3, 4, 0), (span.start, span.end, span.pattern));
+        assert_eq!(None, Literal::new("ab").find_at(b"ab", 3));
+    }
+
+    #[test]
+    fn text_matchers_look_past_invalid_bytes() {
+        let haystack = b"\xFFab\xE9\xFF ab";
+        let spans = |matcher: &dyn Matcher| {
+            matcher.find_all(haystack).iter().map(|s| (s.start, s.end))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(vec![(1, 3), (6, 8)], spans(&CaseInsensitive::new("AB")));
+        assert_eq!(vec![(1, 3), (6, 8)], spans(&Patterns::new(&["ab", "x"], false)));
+        assert_eq!(vec![(1, 3), (6, 8)], spans(&Approximate::new(&["abc"], 1, false)));
+        assert_eq!(Some(Span::new(3, 3)), CaseInsensitive::new("").find_at(b"ab\xFF", 3));
+    }
+
+    #[test]
+    fn approximate_matches_leftmost_first() {
+        let fuzzy = Approximate::new(&["rust", "trusty"], 1, false);
+        let spans: Vec<_> = fuzzy.find_all(b"a rist in trust")
+            .into_iter().map(|s| (s.start, s.end, s.pattern, s.distance))
+            .collect();
+        assert_eq!(vec![(2, 6, 0, 1), (10, 15, 1, 1)], spans);
+        // A line is as close as its closest match, not its first.
+        let fuzzy = Approximate::new(&["rust"], 1, false);
+        let found = search(&fuzzy, b"a rist in trust");
+        assert_eq!((0, vec![(2, 6), (11, 15)]),
+                   (found[0].distance, found[0].spans.clone()));
+    }
+
+    #[test]
+    fn regex_captures() {
+        let re = Regexes::new(&["z", r"(\w)=(\w)"], false).unwrap();
+        let caps = re.captures_at(b"a=b c=d", 1).unwrap();
+        assert_eq!((Some((4, 7)), Some((6, 7))), (caps.get(0), caps.get(2)));
+        assert!(Literal::new("a").captures_at(b"a", 0).is_none());
+        assert!(Regexes::new(&["("], false).is_err());
+    }
+}
@@ -0,0 +1,219 @@
+//! Fast byte and substring search.
+//!
+//! `memchr` and `memrchr` look at a word of input at a time, stopping only
+//! at words that hold the byte.  `Finder` looks for a substring by scanning
+//! a word at a time for its two rarest bytes, each where the needle has it,
+//! and checking the whole needle only where both are.  Should that pair
+//! turn out to be common in the haystack, it switches to
+//! Boyer-Moore-Horspool, whose skips do not depend on how common any byte
+//! is.
+
+use std::cmp;
+use std::convert::TryInto;
+
+const WORD: usize = 8;
+const LO: u64 = 0x0101_0101_0101_0101;
+const HI: u64 = 0x8080_8080_8080_8080;
+
+/// Bytes in roughly decreasing order of how common they are in source
+/// code and prose.  Those not listed are taken to be rarer than any that
+/// are.
+static COMMON: &[u8] = b" etaoinsrlhdcu\nmpfgy.,_wb()v;=:\"-k'/TSAEIRNC*0x1\
+{}LOMDP2BF#<>[]HUWGjq3z$|V45K9678&!Y?+XJQZ%@\\~^`\t";
+
+/// Whether the word `x` has a zero byte.
+fn has_zero(x: u64) -> bool {
+    zero_bits(x) != 0
+}
+
+/// Bits set in `x` if and only if it has a zero byte, though not only in
+/// the zero bytes: `zeros` says which they are.
+fn zero_bits(x: u64) -> u64 {
+    x.wrapping_sub(LO) & !x & HI
+}
+
+/// The high bit of each zero byte of `x`, and no other bits.
+fn zeros(x: u64) -> u64 {
+    !(((x & !HI) + !HI) | x) & HI
+}
+
+/// The word at the start of `bytes`, its first byte lowest.
+fn word(bytes: &[u8]) -> u64 {
+    u64::from_le_bytes(bytes[..WORD].try_into().unwrap())
+}
+
+/// Index of the first `needle` in `haystack`.
+pub fn memchr(needle: u8, haystack: &[u8]) -> Option<usize> {
+    let repeated = LO * needle as u64;
+    let mut i = 0;
+    // Four words at a time while none has the byte, as when it is rare;
+    // then a word at a time to find it.
+    while i + 4 * WORD <= haystack.len() {
+        let block = &haystack[i..i + 4 * WORD];
+        let found = block.chunks_exact(WORD)
+            .fold(0, |found, w| found | zero_bits(word(w) ^ repeated));
+        if found != 0 {
+            break;
+        }
+        i += 4 * WORD;
+    }
+    while i + WORD <= haystack.len() {
+        if has_zero(word(&haystack[i..]) ^ repeated) {
+            break;
+        }
+        i += WORD;
+    }
+    haystack[i..].iter().position(|&b| b == needle).map(|j| i + j)
+}
+
+/// Index of the last `needle` in `haystack`.
+pub fn memrchr(needle: u8, haystack: &[u8]) -> Option<usize> {
+    let repeated = LO * needle as u64;
+    let mut end = haystack.len();
+    while end >= WORD {
+        if has_zero(word(&haystack[end - WORD..]) ^ repeated) {
+            break;
+        }
+        end -= WORD;
+    }
+    haystack[..end].iter().rposition(|&b| b == needle)
+}
+
+/// Number of times `needle` appears in `haystack`.
+pub fn count(needle: u8, haystack: &[u8]) -> usize {
+    let repeated = LO * needle as u64;
+    let words = haystack.chunks_exact(WORD);
+    let rest = words.remainder();
+    words.map(|w| zeros(word(w) ^ repeated).count_ones() as usize).sum::<usize>()
+        + rest.iter().filter(|&&b| b == needle).count()
+}
+
+/// A substring to look for in many haystacks.
+#[derive(Debug, Clone)]
+pub struct Finder {
+    needle: Vec<u8>,
+    /// Indexes in the needle of its rarest byte and of its next rarest
+    /// other than that one.  They are equal only if the needle has just one
+    /// distinct byte.
+    rare: (usize, usize),
+    /// How far the needle can move when the haystack byte under its last
+    /// byte is each of the 256 bytes.
+    skip: Vec<usize>,
+}
+
+impl Finder {
+    pub fn new(needle: &[u8]) -> Finder {
+        let rank = |b: u8| COMMON.iter().position(|&c| c == b)
+            .map_or(0, |i| COMMON.len() - i);
+        let rarest = |exclude: Option<u8>| (0..needle.len())
+            .filter(|&i| Some(needle[i]) != exclude)
+            .min_by_key(|&i| rank(needle[i]));
+        let first = rarest(None).unwrap_or(0);
+        let second = needle.get(first).and_then(|&b| rarest(Some(b)))
+            .unwrap_or(first);
+        let mut skip = vec![needle.len(); 256];
+        for (i, &b) in needle.iter().enumerate().take(needle.len().saturating_sub(1)) {
+            skip[b as usize] = needle.len() - 1 - i;
+        }
+        Finder { needle: needle.to_vec(), rare: (first, second), skip }
+    }
+
+    pub fn needle(&self) -> &[u8] {
+        &self.needle
+    }
+
+    /// Index of the first occurrence of the needle in `haystack`.
+    pub fn find(&self, haystack: &[u8]) -> Option<usize> {
+        let n = self.needle.len();
+        if n == 0 {
+            return Some(0);
+        }
+        if haystack.len() < n {
+            return None;
+        }
+        let (i, j) = self.rare;
+        let (first, second) = (LO * self.needle[i] as u64, LO * self.needle[j] as u64);
+        let last = haystack.len() - n;
+        let mut at = 0;
+        let mut misses = 0;
+        // Look at the needle's possible starts a word at a time; each bit
+        // set in `both` is one where the two rare bytes are in place.
+        while at + WORD <= last + 1 {
+            let mut both = zeros(word(&haystack[at + i..]) ^ first)
+                & zeros(word(&haystack[at + j..]) ^ second);
+            while both != 0 {
+                let start = at + both.trailing_zeros() as usize / 8;
+                if haystack[start..start + n] == self.needle[..] {
+                    return Some(start);
+                }
+                both &= both - 1;
+                misses += 1;
+            }
+            at += WORD;
+            // The rare bytes are not rare here: checking for the needle
+            // wherever they are costs more than it saves.
+            if misses >= 16 && at < misses * 4 * cmp::max(n, WORD) {
+                return self.horspool(haystack, at);
+            }
+        }
+        (at..last + 1).find(|&start| haystack[start..start + n] == self.needle[..])
+    }
+
+    /// Boyer-Moore-Horspool, from `at` on.
+    fn horspool(&self, haystack: &[u8], mut at: usize) -> Option<usize> {
+        let n = self.needle.len();
+        while at + n <= haystack.len() {
+            let end = haystack[at + n - 1];
+            if end == self.needle[n - 1]
+                && haystack[at..at + n - 1] == self.needle[..n - 1] {
+                return Some(at);
+            }
+            at += self.skip[end as usize];
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn bytes_in_words() {
+        let text = b"0123456789abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ\n";
+        for (i, &b) in text.iter().enumerate() {
+            assert_eq!(Some(i), memchr(b, text));
+            assert_eq!(Some(i), memrchr(b, text));
+        }
+        assert_eq!(None, memchr(b'!', text));
+        assert_eq!(Some(16), memrchr(b'x', b"x_______________x__"));
+        assert_eq!(None, memrchr(b'x', b""));
+        assert_eq!(2, count(b'\n', b"a\nb\n"));
+        assert_eq!(11, count(b'x', b"xx_xxxxxxxx_x_"));
+    }
+
+    fn naive(needle: &[u8], haystack: &[u8]) -> Option<usize> {
+        (0..haystack.len() + 1).find(|&i| haystack[i..].starts_with(needle))
+    }
+
+    #[test]
+    fn finds_substrings() {
+        let haystack = b"the quick brown fox jumps over the lazy dog, zzz";
+        for needle in [&b"fox"[..], b"the", b"dog,", b"zzz", b"zzzz", b"q", b"",
+                       b"over the lazy"] {
+            assert_eq!(naive(needle, haystack), Finder::new(needle).find(haystack),
+                       "{:?}", String::from_utf8_lossy(needle));
+        }
+    }
+
+    #[test]
+    fn switches_when_the_rare_bytes_are_common() {
+        // "zq" is rare in general but everywhere in this haystack.
+        let mut haystack = "zq".repeat(500);
+        haystack.push('a');
+        let finder = Finder::new(b"zqzqa");
+        assert_eq!(Some(996), finder.find(haystack.as_bytes()));
+        assert_eq!(naive(b"aza", b"zzzaza"), Finder::new(b"aza").find(b"zzzaza"));
+        assert_eq!(Some(9), Finder::new(b"xxx").find(b"xx_xx_xx_xxx"));
+    }
+}
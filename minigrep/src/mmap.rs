@@ -0,0 +1,116 @@
+//! Reading whole files by mapping them into memory.
+//!
+//! Big files are searched faster as a map than through a buffer: nothing is
+//! copied, and the search sees the whole file at once.  For small files
+//! setting up the map costs more than it saves, so `should_map` decides by
+//! size.  Maps are only made on Unix, through the C library that the
+//! standard library already links.
+//!
+//! A file that is cut short while it is mapped can kill the process with
+//! `SIGBUS`, as it can for any program that maps files.
+
+use std::fs::File;
+use std::io;
+use std::ops::Deref;
+
+/// Files at least this big are mapped.
+pub const MIN_SIZE: u64 = 1024 * 1024;
+
+/// Whether a file of `size` bytes is better mapped than read.
+pub fn should_map(size: u64) -> bool {
+    cfg!(all(unix, target_pointer_width = "64")) && size >= MIN_SIZE
+}
+
+#[cfg(all(unix, target_pointer_width = "64"))]
+mod sys {
+    use std::os::raw::{c_int, c_void};
+
+    pub const PROT_READ: c_int = 1;
+    pub const MAP_PRIVATE: c_int = 2;
+    pub const MAP_FAILED: *mut c_void = !0 as *mut c_void;
+
+    extern "C" {
+        pub fn mmap(addr: *mut c_void, len: usize, prot: c_int, flags: c_int,
+                    fd: c_int, offset: i64) -> *mut c_void;
+        pub fn munmap(addr: *mut c_void, len: usize) -> c_int;
+    }
+}
+
+/// A file mapped read-only into memory.
+pub struct Mmap {
+    ptr: *const u8,
+    len: usize,
+}
+
+// The map is read-only, so it can be shared and sent like a `&[u8]`.
+unsafe impl Send for Mmap {}
+unsafe impl Sync for Mmap {}
+
+impl Mmap {
+    /// Map all of `file`.
+    #[cfg(all(unix, target_pointer_width = "64"))]
+    pub fn open(file: &File) -> io::Result<Mmap> {
+        use std::os::unix::io::AsRawFd;
+        use std::ptr;
+
+        let len = file.metadata()?.len() as usize;
+        if len == 0 {
+            // Empty maps are not allowed; there is nothing to map anyway.
+            return Ok(Mmap { ptr: ptr::NonNull::dangling().as_ptr(), len: 0 });
+        }
+        let ptr = unsafe {
+            sys::mmap(ptr::null_mut(), len, sys::PROT_READ, sys::MAP_PRIVATE,
+                      file.as_raw_fd(), 0)
+        };
+        if ptr == sys::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Mmap { ptr: ptr as *const u8, len })
+    }
+
+    #[cfg(not(all(unix, target_pointer_width = "64")))]
+    pub fn open(_file: &File) -> io::Result<Mmap> {
+        Err(io::Error::new(io::ErrorKind::Other, "memory maps are not supported"))
+    }
+}
+
+impl Deref for Mmap {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { ::std::slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        #[cfg(all(unix, target_pointer_width = "64"))]
+        unsafe {
+            if self.len > 0 {
+                sys::munmap(self.ptr as *mut _, self.len);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    #[test]
+    #[cfg(all(unix, target_pointer_width = "64"))]
+    fn maps_files() {
+        let path = env::temp_dir().join(format!("minigrep-mmap-{}", process::id()));
+        fs::write(&path, "one\ntwo\n").unwrap();
+        let map = Mmap::open(&File::open(&path).unwrap()).unwrap();
+        assert_eq!(b"one\ntwo\n", &map[..]);
+        drop(map);
+        fs::write(&path, "").unwrap();
+        let empty = Mmap::open(&File::open(&path).unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(empty.is_empty());
+    }
+}
@@ -0,0 +1,610 @@
+//! Formatting search results.
+
+use std::env;
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::io::IsTerminal;
+use std::mem;
+use std::path::Path;
+use std::time::Instant;
+
+use context::Line;
+use json;
+use replace::Template;
+use stream::Output;
+use {Color, Config, Match, Mode, OutputMode, Show};
+
+/// ANSI styles, as grep uses by default.
+const MATCH: &str = "\x1b[1;31m";
+const PATH: &str = "\x1b[35m";
+const NUMBER: &str = "\x1b[32m";
+const SEPARATOR: &str = "\x1b[36m";
+/// Styles for diffs.
+const REMOVED: &str = "\x1b[31m";
+const ADDED: &str = "\x1b[32m";
+const HEADER: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+/// Bytes of a file's output a `Pipe` holds before handing them on.
+const PIECE: usize = 64 * 1024;
+
+/// Counts reported in JSON output.
+#[derive(Default)]
+struct Stats {
+    searches: usize,
+    searches_with_match: usize,
+    matched_lines: usize,
+    matches: usize,
+}
+
+/// What a `Printer` wrote about one file, or a piece of it, for another to
+/// copy into its own output.
+///
+/// This lets files be searched at once, each into a `Pipe`, and still be
+/// printed whole and one after another.
+pub struct FileOutput {
+    bytes: Vec<u8>,
+    stats: Stats,
+    printed: bool,
+    separate: bool,
+}
+
+/// What a `Printer` writes to.
+pub trait Out: Write {
+    /// Called after each piece of output, with whether a hunk started
+    /// before anything was printed, as for `FileOutput`.  Output that
+    /// holds on to what is written can hand it on from here.
+    fn pass_on(&mut self, _separate: bool) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Out for Box<dyn Write> {}
+
+impl Out for Vec<u8> {}
+
+/// Output for one file, handed on as `FileOutput` in pieces of about
+/// `PIECE` bytes while the file is searched, so that little of it is held
+/// however much there is.
+pub struct Pipe<F> {
+    bytes: Vec<u8>,
+    hand_on: F,
+    /// Some of the output has been handed on already.
+    started: bool,
+}
+
+impl<F: FnMut(FileOutput) -> io::Result<()>> Pipe<F> {
+    pub fn new(hand_on: F) -> Pipe<F> {
+        Pipe { bytes: Vec::new(), hand_on, started: false }
+    }
+
+    /// Hand on what is held as a piece of output.
+    fn piece(&mut self, stats: Stats, printed: bool, separate: bool)
+        -> io::Result<()> {
+        let bytes = mem::take(&mut self.bytes);
+        // Only the first piece can start with a new hunk.
+        let separate = separate && !self.started;
+        self.started = true;
+        (self.hand_on)(FileOutput { bytes, stats, printed, separate })
+    }
+}
+
+impl<F> Write for Pipe<F> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.bytes.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<F: FnMut(FileOutput) -> io::Result<()>> Out for Pipe<F> {
+    fn pass_on(&mut self, separate: bool) -> io::Result<()> {
+        if self.bytes.len() < PIECE {
+            return Ok(());
+        }
+        self.piece(Stats::default(), true, separate)
+    }
+}
+
+/// Writes search results as grep does, with optional prefixes, or as
+/// JSON Lines.
+pub struct Printer<'c, W = Box<dyn Write>> {
+    config: &'c Config,
+    out: W,
+    with_filename: bool,
+    with_context: bool,
+    colored: bool,
+    /// The `--format` template, made once for every match.
+    format: Option<Template>,
+    printed: bool,
+    /// A hunk started before anything was printed, so the `--` before it
+    /// was left out.
+    separate: bool,
+    started: Instant,
+    /// Totals over every file, and for the file being searched.
+    total: Stats,
+    file: Stats,
+}
+
+impl<'c, W: Out> Printer<'c, W> {
+    /// Printer for the results of `config`, naming the file on each line
+    /// when `with_filename` is set.
+    pub fn new(config: &'c Config, out: W, with_filename: bool)
+        -> Printer<'c, W> {
+        let with_context = (config.before_context > 0 || config.after_context > 0)
+            && config.show == Show::Line;
+        let colored = use_color(config.color);
+        let format = match config.show {
+            Show::Format(ref template) => Some(Template::new(template)),
+            _ => None,
+        };
+        Printer { config, out, with_filename, with_context, colored, format,
+                  printed: false, separate: false, started: Instant::now(),
+                  total: Stats::default(), file: Stats::default() }
+    }
+
+    /// Note the start of a search of `path`.
+    pub fn begin(&mut self, path: &Path) -> io::Result<()> {
+        self.file = Stats::default();
+        if self.config.output == OutputMode::Json {
+            writeln!(self.out, r#"{{"type":"begin","data":{{"path":{}}}}}"#,
+                     json::path(path))?;
+        }
+        Ok(())
+    }
+
+    /// Write one piece of search output for the file named `path`.
+    ///
+    /// Hunks of context are separated with `--`, across files too.
+    pub fn output(&mut self, path: &Path, output: Output) -> io::Result<()> {
+        self.write_output(path, output)?;
+        self.out.pass_on(self.separate)
+    }
+
+    fn write_output(&mut self, path: &Path, output: Output) -> io::Result<()> {
+        if self.config.output == OutputMode::Json {
+            return match output {
+                Output::Break => Ok(()),
+                Output::Line(line) => self.json_line(path, line),
+                Output::Binary => writeln!(self.out,
+                    r#"{{"type":"binary","data":{{"path":{}}}}}"#,
+                    json::path(path)),
+            };
+        }
+        let path = &path.to_string_lossy();
+        let line = match output {
+            Output::Break => {
+                if self.with_context && self.printed {
+                    let separator = self.paint(SEPARATOR, "--");
+                    writeln!(self.out, "{}", separator)?;
+                } else {
+                    self.separate = true;
+                }
+                return Ok(());
+            },
+            Output::Line(line) => line,
+            Output::Binary => {
+                self.printed = true;
+                return writeln!(self.out, "Binary file {} matches", path);
+            },
+        };
+
+        match line.matched {
+            Some(ref m) if self.config.show != Show::Line => {
+                return self.each_match(path, &line, m);
+            },
+            _ => {},
+        }
+        // Context lines use '-' where matches use ':', as grep does.
+        let (column, separator) = match line.matched {
+            Some(ref m) => (Some(m.column()), ':'),
+            None => (None, '-'),
+        };
+        let prefix = self.prefix(path, line.line_number, column, line.offset,
+                                 separator);
+        self.printed = true;
+        self.out.write_all(prefix.as_bytes())?;
+        match line.matched {
+            Some(ref m) if self.colored => self.highlight(line.line, &m.spans)?,
+            _ => self.out.write_all(line.line)?,
+        }
+        self.out.write_all(b"\n")
+    }
+
+    /// Write each non-empty match in `line`, or the `--format` template
+    /// expanded for it, on a line of its own.
+    fn each_match(&mut self, path: &str, line: &Line, m: &Match)
+        -> io::Result<()> {
+        for &(start, end) in m.spans.iter().filter(|s| s.1 > s.0) {
+            let prefix = self.prefix(path, line.line_number, Some(start + 1),
+                                     line.offset + start, ':');
+            self.out.write_all(prefix.as_bytes())?;
+            match self.format {
+                Some(ref template) => {
+                    let mut text = Vec::new();
+                    template.expand_match(&*self.config.matcher, line.line, start,
+                                          end, &mut text);
+                    self.out.write_all(&text)?;
+                },
+                None => self.write_painted(MATCH, &line.line[start..end])?,
+            }
+            self.out.write_all(b"\n")?;
+            self.printed = true;
+        }
+        Ok(())
+    }
+
+    /// Write a `match` or `context` event.
+    fn json_line(&mut self, path: &Path, line: Line) -> io::Result<()> {
+        let (kind, pattern, spans) = match line.matched {
+            Some(ref m) => {
+                self.file.matches += m.spans.len();
+                let mut fields = format!(r#""pattern":{},"#, m.pattern);
+                if let Mode::Fuzzy(_) = self.config.mode {
+                    fields.push_str(&format!(r#""distance":{},"#, m.distance));
+                }
+                ("match", fields, &m.spans[..])
+            },
+            None => ("context", String::new(), &[][..]),
+        };
+        let submatches: Vec<String> = spans.iter()
+            .map(|&(start, end)| format!(
+                r#"{{"match":{},"start":{},"end":{}}}"#,
+                json::data(&line.line[start..end]), start, end))
+            .collect();
+        writeln!(self.out,
+                 concat!(r#"{{"type":"{}","data":{{"path":{},"lines":{},"#,
+                         r#""line_number":{},"absolute_offset":{},{}"#,
+                         r#""submatches":[{}]}}}}"#),
+                 kind, json::path(path), json::data(line.line),
+                 line.line_number, line.offset, pattern, submatches.join(","))
+    }
+
+    /// Write what the output mode reports once a file has been searched,
+    /// given the number of matching lines.
+    pub fn summary(&mut self, path: &Path, count: usize) -> io::Result<()> {
+        self.file.searches = 1;
+        self.file.searches_with_match = (count > 0) as usize;
+        self.file.matched_lines = count;
+        self.total.add(&self.file);
+
+        if self.config.output == OutputMode::Json {
+            return writeln!(self.out,
+                r#"{{"type":"end","data":{{"path":{},"stats":{}}}}}"#,
+                json::path(path), self.file.json());
+        }
+        let path = &path.to_string_lossy();
+        match self.config.output {
+            OutputMode::Count if self.with_filename => {
+                let path = self.paint(PATH, path);
+                let separator = self.paint(SEPARATOR, ":");
+                writeln!(self.out, "{}{}{}", path, separator, count)
+            },
+            OutputMode::Count => writeln!(self.out, "{}", count),
+            OutputMode::FilesWithMatches if count > 0 => {
+                let path = self.paint(PATH, path);
+                writeln!(self.out, "{}", path)
+            },
+            OutputMode::FilesWithoutMatch if count == 0 => {
+                let path = self.paint(PATH, path);
+                writeln!(self.out, "{}", path)
+            },
+            _ => Ok(()),
+        }
+    }
+
+    /// Build the `path:line:column:offset:` prefix for a line of output.
+    ///
+    /// Context lines have no column.
+    fn prefix(&self, path: &str, line_number: usize,
+              column: Option<usize>, offset: usize, separator: char) -> String {
+        let separator = self.paint(SEPARATOR, &separator.to_string());
+        let mut prefix = String::new();
+        if self.with_filename {
+            prefix.push_str(&self.paint(PATH, path));
+            prefix.push_str(&separator);
+        }
+        if self.config.line_number {
+            prefix.push_str(&self.paint(NUMBER, &line_number.to_string()));
+            prefix.push_str(&separator);
+        }
+        if let (true, Some(column)) = (self.config.column, column) {
+            prefix.push_str(&self.paint(NUMBER, &column.to_string()));
+            prefix.push_str(&separator);
+        }
+        if self.config.byte_offset {
+            prefix.push_str(&self.paint(NUMBER, &offset.to_string()));
+            prefix.push_str(&separator);
+        }
+        prefix
+    }
+
+    /// `text` in `style`, if colors are on.
+    fn paint(&self, style: &str, text: &str) -> String {
+        if self.colored {
+            format!("{}{}{}", style, text, RESET)
+        } else {
+            text.to_string()
+        }
+    }
+
+    /// Write `bytes` in `style`, if colors are on.
+    fn write_painted(&mut self, style: &str, bytes: &[u8]) -> io::Result<()> {
+        if !self.colored {
+            return self.out.write_all(bytes);
+        }
+        self.out.write_all(style.as_bytes())?;
+        self.out.write_all(bytes)?;
+        self.out.write_all(RESET.as_bytes())
+    }
+
+    /// Write `line` with each of the byte ranges in `spans` highlighted.
+    fn highlight(&mut self, line: &[u8], spans: &[(usize, usize)]) -> io::Result<()> {
+        let mut last = 0;
+        for &(start, end) in spans.iter().filter(|s| s.1 > s.0) {
+            self.out.write_all(&line[last..start])?;
+            self.write_painted(MATCH, &line[start..end])?;
+            last = end;
+        }
+        self.out.write_all(&line[last..])
+    }
+
+    /// Write a unified diff, as `replace::diff` makes it, keeping the
+    /// terminator of each line.
+    pub fn diff(&mut self, diff: &str) -> io::Result<()> {
+        for line in diff.split_inclusive('\n') {
+            let body = line.trim_end_matches('\n').trim_end_matches('\r');
+            let (line, end) = line.split_at(body.len());
+            let style = if line.starts_with("---") || line.starts_with("+++") {
+                HEADER
+            } else if line.starts_with("@@") {
+                SEPARATOR
+            } else if line.starts_with('-') {
+                REMOVED
+            } else if line.starts_with('+') {
+                ADDED
+            } else {
+                ""
+            };
+            let line = if style.is_empty() {
+                line.to_string()
+            } else {
+                self.paint(style, line)
+            };
+            write!(self.out, "{}{}", line, end)?;
+        }
+        self.printed = true;
+        self.out.pass_on(self.separate)
+    }
+
+    /// Write what another printer wrote about a file, as if this one had
+    /// searched it.
+    pub fn append(&mut self, file: FileOutput) -> io::Result<()> {
+        if file.separate && self.with_context && self.printed {
+            let separator = self.paint(SEPARATOR, "--");
+            writeln!(self.out, "{}", separator)?;
+        }
+        self.out.write_all(&file.bytes)?;
+        self.total.add(&file.stats);
+        self.printed |= file.printed;
+        Ok(())
+    }
+
+    /// Write the `summary` event ending JSON output.
+    pub fn finish(&mut self) -> io::Result<()> {
+        if self.config.output == OutputMode::Json {
+            let elapsed = self.started.elapsed();
+            writeln!(self.out,
+                r#"{{"type":"summary","data":{{"elapsed_secs":{},"stats":{}}}}}"#,
+                elapsed.as_secs_f64(), self.total.json())?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+impl<'c, F: FnMut(FileOutput) -> io::Result<()>> Printer<'c, Pipe<F>> {
+    /// Hand on the rest of what has been written, to be `append`ed to
+    /// another printer after the pieces handed on before.
+    pub fn close(mut self) -> io::Result<()> {
+        self.out.piece(self.total, self.printed, self.separate)
+    }
+}
+
+impl Stats {
+    fn add(&mut self, other: &Stats) {
+        self.searches += other.searches;
+        self.searches_with_match += other.searches_with_match;
+        self.matched_lines += other.matched_lines;
+        self.matches += other.matches;
+    }
+
+    fn json(&self) -> String {
+        format!(concat!(r#"{{"searches":{},"searches_with_match":{},"#,
+                        r#""matched_lines":{},"matches":{}}}"#),
+                self.searches, self.searches_with_match, self.matched_lines,
+                self.matches)
+    }
+}
+
+/// Decide whether to color the output.
+///
+/// `Auto` colors only when standard output is a terminal, and never when
+/// `NO_COLOR` is set to anything but the empty string.
+pub fn use_color(color: Color) -> bool {
+    match color {
+        Color::Always => true,
+        Color::Never => false,
+        Color::Auto => {
+            io::stdout().is_terminal()
+                && env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+        },
+    }
+}
+
+/// Standard output, buffered by line unless it is a regular file.
+///
+/// Line buffering lets results through a pipe as soon as they are found,
+/// as in `tail -f log | minigrep ERROR`.
+pub fn stdout() -> Box<dyn Write> {
+    let out = io::stdout().lock();
+    let is_file = fs::metadata("/dev/stdout").is_ok_and(|m| m.is_file());
+    if is_file {
+        Box::new(io::BufWriter::new(out))
+    } else {
+        Box::new(out)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use args::{self, Command};
+
+    fn config(args: &[&str]) -> Config {
+        let args = ["minigrep", "--color=never"].iter().chain(args)
+            .map(|a| a.to_string());
+        match args::parse_with_env(args, |_| None) {
+            Ok(Command::Search(config)) => *config,
+            _ => panic!("expected a search"),
+        }
+    }
+
+    /// A matching line, with matches at `spans`.
+    fn matched(line_number: usize, offset: usize, line: &[u8],
+               spans: Vec<(usize, usize)>) -> Output<'_> {
+        let (start, end) = spans[0];
+        Output::Line(Line { line_number, offset, line, matched: Some(Match {
+            line_number, offset, start, end, pattern: 0, distance: 0, spans,
+            line: line.into() }) })
+    }
+
+    /// A line of context.
+    fn context(line_number: usize, offset: usize, line: &[u8]) -> Output<'_> {
+        Output::Line(Line { line_number, offset, line, matched: None })
+    }
+
+    /// What a printer for `args` writes about a file with `outputs`.
+    fn printed(args: &[&str], with_filename: bool, outputs: Vec<Output>) -> Vec<u8> {
+        let config = config(args);
+        let mut printer = Printer::new(&config, Vec::new(), with_filename);
+        let path = Path::new("file");
+        let count = outputs.iter()
+            .filter(|o| matches!(o, Output::Line(l) if l.matched.is_some()))
+            .count();
+        printer.begin(path).unwrap();
+        for output in outputs {
+            printer.output(path, output).unwrap();
+        }
+        printer.summary(path, count).unwrap();
+        printer.out
+    }
+
+    #[test]
+    fn prefixes() {
+        let outputs = || vec![
+            Output::Break, context(1, 0, b"before"), matched(2, 7, b"axb", vec![(1, 2)]),
+            Output::Break, matched(9, 40, b"x", vec![(0, 1)]),
+        ];
+        assert_eq!(&b"file-1-0-before\nfile:2:2:7:axb\n--\nfile:9:1:40:x\n"[..],
+                   &printed(&["-n", "--column", "-b", "-C1", "x"], true, outputs())[..]);
+        assert_eq!(&b"before\naxb\n--\nx\n"[..],
+                   &printed(&["-C1", "x"], false, outputs())[..]);
+    }
+
+    #[test]
+    fn highlighting() {
+        let outputs = vec![Output::Break,
+                           matched(1, 0, b"axbx\xFF", vec![(1, 2), (2, 2), (3, 4)])];
+        assert_eq!(&b"a\x1b[1;31mx\x1b[0mb\x1b[1;31mx\x1b[0m\xFF\n"[..],
+                   &printed(&["--color=always", "x"], false, outputs)[..]);
+    }
+
+    #[test]
+    fn only_matching_and_format() {
+        let outputs = || vec![Output::Break,
+                              matched(1, 10, b"axbx\xFF", vec![(0, 2), (2, 4)])];
+        assert_eq!(&b"10:ax\n12:bx\n"[..],
+                   &printed(&["-o", "-b", "x"], false, outputs())[..]);
+        assert_eq!(&b"1:[a]\n3:[b]\n"[..],
+                   &printed(&["--column", "--format", "[$1]", "-E", "(\\w)x"], false,
+                            outputs())[..]);
+    }
+
+    #[test]
+    fn json_events() {
+        let outputs = vec![Output::Break, matched(1, 0, b"x\xFFab", vec![(2, 4)]),
+                           context(2, 5, b"after")];
+        let json = printed(&["--json", "-A1", "ab"], false, outputs);
+        assert_eq!(vec![
+            r#"{"type":"begin","data":{"path":{"text":"file"}}}"#,
+            concat!(r#"{"type":"match","data":{"path":{"text":"file"},"#,
+                    r#""lines":{"bytes":"eP9hYg=="},"line_number":1,"#,
+                    r#""absolute_offset":0,"pattern":0,"#,
+                    r#""submatches":[{"match":{"text":"ab"},"start":2,"end":4}]}}"#),
+            concat!(r#"{"type":"context","data":{"path":{"text":"file"},"#,
+                    r#""lines":{"text":"after"},"line_number":2,"absolute_offset":5,"#,
+                    r#""submatches":[]}}"#),
+            concat!(r#"{"type":"end","data":{"path":{"text":"file"},"#,
+                    r#""stats":{"searches":1,"searches_with_match":1,"#,
+                    r#""matched_lines":1,"matches":1}}}"#),
+        ], String::from_utf8(json).unwrap().lines().collect::<Vec<_>>());
+    }
+
+    /// Print a hunk of `lines` lines of context, as for one file.
+    fn hunk<W: Out>(printer: &mut Printer<W>, lines: usize) -> io::Result<()> {
+        let path = Path::new("file");
+        printer.output(path, Output::Break)?;
+        for n in 1..lines + 1 {
+            let text = format!("line {} of the file", n);
+            printer.output(path, Output::Line(Line {
+                line_number: n, offset: 0, line: text.as_bytes(), matched: None }))?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn pipes_hand_on_pieces() {
+        let config = config(&["-C1", "q"]);
+        let mut direct = Printer::new(&config, Vec::new(), false);
+        let mut appended = Printer::new(&config, Vec::new(), false);
+        for &lines in &[5000, 3] {
+            hunk(&mut direct, lines).unwrap();
+            let mut pieces = Vec::new();
+            let mut printer = Printer::new(&config, Pipe::new(|piece| {
+                pieces.push(piece);
+                Ok(())
+            }), false);
+            hunk(&mut printer, lines).unwrap();
+            printer.close().unwrap();
+            assert_eq!(lines > 1000, pieces.len() > 1);
+            for piece in pieces {
+                appended.append(piece).unwrap();
+            }
+        }
+        assert_eq!(String::from_utf8_lossy(&direct.out),
+                   String::from_utf8_lossy(&appended.out));
+        assert_eq!(1, String::from_utf8_lossy(&appended.out).matches("--").count());
+    }
+
+    #[test]
+    fn diffs_keep_terminators() {
+        let diff = "--- a/f\n+++ b/f\n@@ -1 +1 @@\n-one\r\n+two\r\n";
+        let plain = config(&["q"]);
+        let mut printer = Printer::new(&plain, Vec::new(), false);
+        printer.diff(diff).unwrap();
+        assert_eq!(diff.as_bytes(), &printer.out[..]);
+        let colored = config(&["--color=always", "q"]);
+        let mut printer = Printer::new(&colored, Vec::new(), false);
+        printer.diff("-one\r\n").unwrap();
+        assert_eq!(format!("{}-one{}\r\n", REMOVED, RESET).as_bytes(),
+                   &printer.out[..]);
+    }
+}
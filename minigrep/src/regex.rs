@@ -0,0 +1,1172 @@
+//! A small regular expression engine for minigrep.
+//!
+//! Patterns are parsed into a syntax tree, compiled into a program for a
+//! Pike VM, and run over bytes so that the search never needs to backtrack.
+//! Supported syntax: literals, `.`, character classes (`[a-z]`, `[^0-9]`,
+//! `\d \w \s` and their negations, `[[:alpha:]]`), alternation `|`, groups
+//! `(...)`, `(?:...)` and `(?P<name>...)`, repetition `* + ? {n} {n,}
+//! {n,m}` (append `?` for lazy), anchors `^ $ \A \z \b \B` and the inline
+//! flags `(?i)`, `(?m)` and `(?s)`.
+
+use std::error;
+use std::fmt;
+use std::mem;
+use std::str;
+
+/// Largest count accepted in a `{n,m}` repetition.
+const MAX_REPEAT: u32 = 1000;
+
+/// Largest compiled program accepted, in instructions.
+const MAX_PROGRAM: usize = 100_000;
+
+/// Deepest nesting of groups accepted; parsing and compiling recurse once
+/// per level.
+const MAX_NESTING: usize = 250;
+
+/// A pattern that failed to compile.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Error {
+    /// The pattern as given.
+    pub pattern: String,
+    /// Character offset in the pattern where the problem was found.
+    pub position: usize,
+    /// What went wrong.
+    pub message: String,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "regex parse error at position {} in \"{}\": {}",
+               self.position, self.pattern, self.message)
+    }
+}
+
+impl error::Error for Error {}
+
+/// A compiled regular expression.
+#[derive(Debug, Clone)]
+pub struct Regex {
+    pattern: String,
+    prog: Vec<Inst>,
+    names: Vec<Option<String>>,
+}
+
+/// Byte offsets of each capture group of a match.
+///
+/// Group 0 is the whole match.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Captures {
+    slots: Vec<Option<usize>>,
+    names: Vec<Option<String>>,
+}
+
+impl Captures {
+    /// Span of group `i`, if the group took part in the match.
+    pub fn get(&self, i: usize) -> Option<(usize, usize)> {
+        match (self.slots.get(i * 2), self.slots.get(i * 2 + 1)) {
+            (Some(&Some(s)), Some(&Some(e))) => Some((s, e)),
+            _ => None,
+        }
+    }
+
+    /// Span of the group called `name`, if it took part in the match.
+    pub fn name(&self, name: &str) -> Option<(usize, usize)> {
+        self.names.iter()
+            .position(|n| n.as_ref().is_some_and(|n| n == name))
+            .and_then(|i| self.get(i))
+    }
+
+    /// Number of groups, including group 0.
+    pub fn len(&self) -> usize {
+        self.slots.len() / 2
+    }
+
+    /// Always false, since group 0 is always present.
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+}
+
+impl Regex {
+    /// Compile a pattern.
+    pub fn new(pattern: &str) -> Result<Regex, Error> {
+        Regex::compile(pattern, Flags::default())
+    }
+
+    /// Compile a pattern, as if it started with `(?i)`.
+    pub fn new_case_insensitive(pattern: &str) -> Result<Regex, Error> {
+        let flags = Flags { case_insensitive: true, ..Flags::default() };
+        Regex::compile(pattern, flags)
+    }
+
+    /// Compile a pattern to search text of many lines, as if it started
+    /// with `(?m)` so that `^` and `$` match at the ends of each line, and
+    /// with `(?i)` too if `case_insensitive`.
+    pub fn new_multi_line(pattern: &str, case_insensitive: bool)
+        -> Result<Regex, Error> {
+        let flags = Flags { case_insensitive, multi_line: true, ..Flags::default() };
+        Regex::compile(pattern, flags)
+    }
+
+    fn compile(pattern: &str, flags: Flags) -> Result<Regex, Error> {
+        let mut parser = Parser {
+            pattern,
+            chars: pattern.chars().collect(),
+            pos: 0,
+            flags,
+            names: vec![None],
+            depth: 0,
+        };
+        let ast = parser.parse()?;
+        let mut compiler = Compiler { prog: Vec::new() };
+        compiler.push(Inst::Save(0));
+        compiler.node(&ast);
+        compiler.push(Inst::Save(1));
+        compiler.push(Inst::Match);
+        if compiler.prog.len() > MAX_PROGRAM {
+            return Err(Error {
+                pattern: pattern.to_string(),
+                position: 0,
+                message: String::from("pattern is too large"),
+            });
+        }
+
+        Ok(Regex { pattern: pattern.to_string(), prog: compiler.prog,
+                   names: parser.names })
+    }
+
+    /// The pattern this was compiled from.
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+
+    /// Number of capture groups, including group 0.
+    pub fn captures_len(&self) -> usize {
+        self.names.len()
+    }
+
+    /// Check if the pattern matches anywhere in `text`.
+    pub fn is_match(&self, text: &str) -> bool {
+        self.find_at(text.as_bytes(), 0).is_some()
+    }
+
+    /// Find the leftmost match in `text`.
+    pub fn find(&self, text: &str) -> Option<(usize, usize)> {
+        self.find_at(text.as_bytes(), 0)
+    }
+
+    /// Find the leftmost match starting at or after `start`.
+    ///
+    /// Anchors and word boundaries see the whole of `text`, not just the
+    /// part after `start`.
+    pub fn find_at(&self, text: &[u8], start: usize)
+        -> Option<(usize, usize)> {
+        self.captures_at(text, start).and_then(|caps| caps.get(0))
+    }
+
+    /// Find the leftmost match in `text` along with its capture groups.
+    pub fn captures(&self, text: &str) -> Option<Captures> {
+        self.captures_at(text.as_bytes(), 0)
+    }
+
+    /// Find the leftmost match at or after `start`, with capture groups.
+    pub fn captures_at(&self, text: &[u8], start: usize) -> Option<Captures> {
+        self.captures_where(text, start, &|_| true, &|_| true)
+    }
+
+    /// Find the leftmost match at or after `start` that begins at a
+    /// position `starts` accepts and ends at one `ends` accepts, with
+    /// capture groups.  Other ways of matching are still tried when one
+    /// ends in the wrong place, so `a|ab` finds "ab" where only the end of
+    /// the text will do.
+    pub fn captures_where(&self, text: &[u8], start: usize,
+                          starts: &dyn Fn(usize) -> bool,
+                          ends: &dyn Fn(usize) -> bool) -> Option<Captures> {
+        if start > text.len() {
+            return None;
+        }
+        Vm::new(self, text, starts, ends).run(start).map(|slots| {
+            Captures { slots, names: self.names.clone() }
+        })
+    }
+}
+
+// ---------------------------------------------------------------------
+// Syntax tree
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Flags {
+    case_insensitive: bool,
+    multi_line: bool,
+    dot_all: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Look {
+    StartText,
+    EndText,
+    StartLine,
+    EndLine,
+    WordBoundary,
+    NotWordBoundary,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Perl {
+    Digit,
+    Word,
+    Space,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Posix {
+    Alnum,
+    Alpha,
+    Digit,
+    Lower,
+    Punct,
+    Space,
+    Upper,
+    Word,
+    XDigit,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum ClassItem {
+    Range(char, char),
+    Perl(Perl, bool),
+    Posix(Posix),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Class {
+    items: Vec<ClassItem>,
+    negated: bool,
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Empty,
+    Char(char, bool),
+    Any(bool),
+    Class(Class, bool),
+    Look(Look),
+    Group(Box<Node>, Option<usize>),
+    Concat(Vec<Node>),
+    Alternate(Vec<Node>),
+    Repeat { node: Box<Node>, min: u32, max: Option<u32>, greedy: bool },
+}
+
+struct Parser<'p> {
+    pattern: &'p str,
+    chars: Vec<char>,
+    pos: usize,
+    flags: Flags,
+    names: Vec<Option<String>>,
+    /// Number of groups the parser is inside.
+    depth: usize,
+}
+
+impl<'p> Parser<'p> {
+    fn parse(&mut self) -> Result<Node, Error> {
+        let node = self.alternation()?;
+        match self.peek() {
+            None => Ok(node),
+            Some(')') => Err(self.error("unmatched ')'")),
+            Some(c) => Err(self.error(&format!("unexpected '{}'", c))),
+        }
+    }
+
+    fn error(&self, message: &str) -> Error {
+        Error {
+            pattern: self.pattern.to_string(),
+            position: self.pos,
+            message: message.to_string(),
+        }
+    }
+
+    fn error_at(&self, position: usize, message: &str) -> Error {
+        Error { position, ..self.error(message) }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).cloned()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek();
+        if c.is_some() {
+            self.pos += 1;
+        }
+        c
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn alternation(&mut self) -> Result<Node, Error> {
+        let mut branches = vec![self.concat()?];
+        while self.eat('|') {
+            branches.push(self.concat()?);
+        }
+        if branches.len() == 1 {
+            Ok(branches.pop().unwrap())
+        } else {
+            Ok(Node::Alternate(branches))
+        }
+    }
+
+    fn concat(&mut self) -> Result<Node, Error> {
+        let mut items = Vec::new();
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            match self.atom()? {
+                Some(atom) => {
+                    let atom = self.repetition(atom)?;
+                    items.push(atom);
+                },
+                None => continue,
+            }
+        }
+        Ok(match items.len() {
+            0 => Node::Empty,
+            1 => items.pop().unwrap(),
+            _ => Node::Concat(items),
+        })
+    }
+
+    /// Parse one atom. Returns `None` for a bare flag group like `(?i)`.
+    fn atom(&mut self) -> Result<Option<Node>, Error> {
+        let start = self.pos;
+        let c = self.next().unwrap();
+        let ci = self.flags.case_insensitive;
+        let node = match c {
+            '.' => Node::Any(self.flags.dot_all),
+            '^' => Node::Look(if self.flags.multi_line
+                { Look::StartLine } else { Look::StartText }),
+            '$' => Node::Look(if self.flags.multi_line
+                { Look::EndLine } else { Look::EndText }),
+            '(' => return self.group(start),
+            '[' => Node::Class(self.class(start)?, ci),
+            '\\' => self.escape()?,
+            '*' | '+' | '?' => {
+                return Err(self.error_at(start,
+                    "repetition operator missing expression"));
+            },
+            '{' if self.counted_repetition_follows(start) => {
+                return Err(self.error_at(start,
+                    "repetition operator missing expression"));
+            },
+            c => Node::Char(c, ci),
+        };
+        Ok(Some(node))
+    }
+
+    fn group(&mut self, start: usize) -> Result<Option<Node>, Error> {
+        // Inline flags only last until the end of the enclosing group, so
+        // those of `(?i:...)` end with it.
+        let saved_flags = self.flags;
+        let mut index = None;
+        if self.eat('?') {
+            if self.eat('P') && self.peek() != Some('<') {
+                return Err(self.error("expected '<' after '(?P'"));
+            }
+            if self.eat('<') {
+                let name = self.group_name()?;
+                index = Some(self.names.len());
+                self.names.push(Some(name));
+            } else if !self.flag_group()? {
+                return Ok(None);
+            }
+        } else {
+            index = Some(self.names.len());
+            self.names.push(None);
+        }
+
+        if self.depth == MAX_NESTING {
+            return Err(self.error_at(start, "groups are nested too deeply"));
+        }
+        self.depth += 1;
+        let inner = self.alternation()?;
+        self.depth -= 1;
+        self.flags = saved_flags;
+        if !self.eat(')') {
+            return Err(self.error_at(start, "unclosed group"));
+        }
+        Ok(Some(Node::Group(Box::new(inner), index)))
+    }
+
+    fn group_name(&mut self) -> Result<String, Error> {
+        let start = self.pos;
+        let mut name = String::new();
+        loop {
+            match self.next() {
+                Some('>') => break,
+                Some(c) if c.is_alphanumeric() || c == '_' => name.push(c),
+                Some(_) => {
+                    return Err(self.error_at(self.pos - 1,
+                        "invalid character in group name"));
+                },
+                None => return Err(self.error_at(start, "unclosed group name")),
+            }
+        }
+        if name.is_empty() {
+            return Err(self.error_at(start, "empty group name"));
+        }
+        if self.names.iter().any(|n| n.as_ref() == Some(&name)) {
+            return Err(self.error_at(start,
+                &format!("duplicate group name '{}'", name)));
+        }
+        Ok(name)
+    }
+
+    /// Parse the flags of `(?flags)` or `(?flags:`.
+    ///
+    /// Returns true when a group body follows, false for a bare flag group.
+    fn flag_group(&mut self) -> Result<bool, Error> {
+        let mut enable = true;
+        loop {
+            match self.next() {
+                Some(':') => return Ok(true),
+                Some(')') => return Ok(false),
+                Some('-') if enable => enable = false,
+                Some('i') => self.flags.case_insensitive = enable,
+                Some('m') => self.flags.multi_line = enable,
+                Some('s') => self.flags.dot_all = enable,
+                Some(c) => {
+                    return Err(self.error_at(self.pos - 1,
+                        &format!("unrecognized flag '{}'", c)));
+                },
+                None => return Err(self.error("unclosed group")),
+            }
+        }
+    }
+
+    fn escape(&mut self) -> Result<Node, Error> {
+        let start = self.pos - 1;
+        let ci = self.flags.case_insensitive;
+        let c = match self.next() {
+            Some(c) => c,
+            None => return Err(self.error_at(start,
+                "pattern ends with a trailing backslash")),
+        };
+        let node = match c {
+            'd' => Node::Class(perl_class(Perl::Digit, false), ci),
+            'D' => Node::Class(perl_class(Perl::Digit, true), ci),
+            'w' => Node::Class(perl_class(Perl::Word, false), ci),
+            'W' => Node::Class(perl_class(Perl::Word, true), ci),
+            's' => Node::Class(perl_class(Perl::Space, false), ci),
+            'S' => Node::Class(perl_class(Perl::Space, true), ci),
+            'b' => Node::Look(Look::WordBoundary),
+            'B' => Node::Look(Look::NotWordBoundary),
+            'A' => Node::Look(Look::StartText),
+            'z' => Node::Look(Look::EndText),
+            _ => Node::Char(self.escaped_char(c, start)?, ci),
+        };
+        Ok(node)
+    }
+
+    /// Decode an escape that stands for a single character.
+    fn escaped_char(&mut self, c: char, start: usize) -> Result<char, Error> {
+        match c {
+            'n' => Ok('\n'),
+            't' => Ok('\t'),
+            'r' => Ok('\r'),
+            'f' => Ok('\x0C'),
+            'v' => Ok('\x0B'),
+            '0' => Ok('\0'),
+            'x' => self.hex_escape(start),
+            c if c.is_ascii_alphanumeric() => Err(self.error_at(start,
+                &format!("unrecognized escape sequence '\\{}'", c))),
+            c => Ok(c),
+        }
+    }
+
+    fn hex_escape(&mut self, start: usize) -> Result<char, Error> {
+        let mut digits = String::new();
+        if self.eat('{') {
+            loop {
+                match self.next() {
+                    Some('}') => break,
+                    Some(c) => digits.push(c),
+                    None => return Err(self.error_at(start,
+                        "unclosed hexadecimal escape")),
+                }
+            }
+        } else {
+            for _ in 0..2 {
+                match self.next() {
+                    Some(c) => digits.push(c),
+                    None => return Err(self.error_at(start,
+                        "incomplete hexadecimal escape")),
+                }
+            }
+        }
+        u32::from_str_radix(&digits, 16).ok()
+            .and_then(::std::char::from_u32)
+            .ok_or_else(|| self.error_at(start,
+                "invalid hexadecimal escape"))
+    }
+
+    fn class(&mut self, start: usize) -> Result<Class, Error> {
+        let negated = self.eat('^');
+        let mut items = Vec::new();
+        let mut first = true;
+        loop {
+            let c = match self.next() {
+                Some(c) => c,
+                None => return Err(self.error_at(start,
+                    "unclosed character class")),
+            };
+            if c == ']' && !first {
+                break;
+            }
+            first = false;
+
+            let item_start = self.pos - 1;
+            let low = match c {
+                '[' if self.peek() == Some(':') => {
+                    items.push(ClassItem::Posix(self.posix_class()?));
+                    continue;
+                },
+                '\\' => match self.next() {
+                    Some('d') => { items.push(ClassItem::Perl(Perl::Digit, false)); continue; },
+                    Some('D') => { items.push(ClassItem::Perl(Perl::Digit, true)); continue; },
+                    Some('w') => { items.push(ClassItem::Perl(Perl::Word, false)); continue; },
+                    Some('W') => { items.push(ClassItem::Perl(Perl::Word, true)); continue; },
+                    Some('s') => { items.push(ClassItem::Perl(Perl::Space, false)); continue; },
+                    Some('S') => { items.push(ClassItem::Perl(Perl::Space, true)); continue; },
+                    Some(e) => self.escaped_char(e, item_start)?,
+                    None => return Err(self.error_at(start,
+                        "unclosed character class")),
+                },
+                c => c,
+            };
+
+            // A '-' is a range unless it is the last thing in the class.
+            if self.peek() == Some('-')
+                && self.chars.get(self.pos + 1).is_some_and(|&c| c != ']') {
+                self.pos += 1;
+                let high = match self.next() {
+                    Some('\\') => {
+                        let e = self.next().unwrap_or('\\');
+                        self.escaped_char(e, self.pos - 2)?
+                    },
+                    Some(c) => c,
+                    None => return Err(self.error_at(start,
+                        "unclosed character class")),
+                };
+                if high < low {
+                    return Err(self.error_at(item_start,
+                        &format!("invalid character class range {}-{}",
+                                 low, high)));
+                }
+                items.push(ClassItem::Range(low, high));
+            } else {
+                items.push(ClassItem::Range(low, low));
+            }
+        }
+        Ok(Class { items, negated })
+    }
+
+    fn posix_class(&mut self) -> Result<Posix, Error> {
+        let start = self.pos - 1;
+        self.pos += 1;
+        let mut name = String::new();
+        while let Some(c) = self.next() {
+            if c == ':' {
+                break;
+            }
+            name.push(c);
+        }
+        if !self.eat(']') {
+            return Err(self.error_at(start, "unclosed POSIX class"));
+        }
+        match name.as_str() {
+            "alnum" => Ok(Posix::Alnum),
+            "alpha" => Ok(Posix::Alpha),
+            "digit" => Ok(Posix::Digit),
+            "lower" => Ok(Posix::Lower),
+            "punct" => Ok(Posix::Punct),
+            "space" => Ok(Posix::Space),
+            "upper" => Ok(Posix::Upper),
+            "word" => Ok(Posix::Word),
+            "xdigit" => Ok(Posix::XDigit),
+            _ => Err(self.error_at(start,
+                &format!("unknown POSIX class '{}'", name))),
+        }
+    }
+
+    /// Check for `{n}`, `{n,}` or `{n,m}` at the current position.
+    fn counted_repetition_follows(&self, brace: usize) -> bool {
+        let mut i = brace + 1;
+        let mut digits = 0;
+        while i < self.chars.len() && self.chars[i].is_ascii_digit() {
+            i += 1;
+            digits += 1;
+        }
+        if digits == 0 {
+            return false;
+        }
+        if i < self.chars.len() && self.chars[i] == ',' {
+            i += 1;
+            while i < self.chars.len() && self.chars[i].is_ascii_digit() {
+                i += 1;
+            }
+        }
+        i < self.chars.len() && self.chars[i] == '}'
+    }
+
+    fn repetition(&mut self, mut node: Node) -> Result<Node, Error> {
+        loop {
+            let start = self.pos;
+            let (min, max) = match self.peek() {
+                Some('*') => { self.pos += 1; (0, None) },
+                Some('+') => { self.pos += 1; (1, None) },
+                Some('?') => { self.pos += 1; (0, Some(1)) },
+                Some('{') if self.counted_repetition_follows(start) => {
+                    self.pos += 1;
+                    self.counted_repetition(start)?
+                },
+                _ => return Ok(node),
+            };
+            if let Node::Look(_) = node {
+                return Err(self.error_at(start,
+                    "repetition operator applied to an anchor"));
+            }
+            let greedy = !self.eat('?');
+            node = Node::Repeat { node: Box::new(node), min, max, greedy };
+        }
+    }
+
+    fn counted_repetition(&mut self, start: usize)
+        -> Result<(u32, Option<u32>), Error> {
+        let min = self.number(start)?;
+        let max = if self.eat(',') {
+            if self.peek() == Some('}') { None } else { Some(self.number(start)?) }
+        } else {
+            Some(min)
+        };
+        self.eat('}');
+        if let Some(max) = max {
+            if max < min {
+                return Err(self.error_at(start,
+                    &format!("invalid repetition range {{{},{}}}", min, max)));
+            }
+        }
+        Ok((min, max))
+    }
+
+    fn number(&mut self, start: usize) -> Result<u32, Error> {
+        let mut n: u32 = 0;
+        while let Some(d) = self.peek().and_then(|c| c.to_digit(10)) {
+            self.pos += 1;
+            n = n.saturating_mul(10).saturating_add(d);
+        }
+        if n > MAX_REPEAT {
+            return Err(self.error_at(start,
+                &format!("repetition count exceeds {}", MAX_REPEAT)));
+        }
+        Ok(n)
+    }
+}
+
+fn perl_class(perl: Perl, negated: bool) -> Class {
+    Class { items: vec![ClassItem::Perl(perl, false)], negated }
+}
+
+// ---------------------------------------------------------------------
+// Compiler
+
+#[derive(Debug, Clone)]
+enum Inst {
+    Char(char, bool),
+    Any(bool),
+    Class(Class, bool),
+    Look(Look),
+    Split(usize, usize),
+    Jump(usize),
+    Save(usize),
+    Match,
+}
+
+struct Compiler {
+    prog: Vec<Inst>,
+}
+
+impl Compiler {
+    fn push(&mut self, inst: Inst) -> usize {
+        self.prog.push(inst);
+        self.prog.len() - 1
+    }
+
+    fn node(&mut self, node: &Node) {
+        // Stop growing once the program is too large; the caller reports it.
+        if self.prog.len() > MAX_PROGRAM {
+            return;
+        }
+        match *node {
+            Node::Empty => {},
+            Node::Char(c, ci) => { self.push(Inst::Char(c, ci)); },
+            Node::Any(dot_all) => { self.push(Inst::Any(dot_all)); },
+            Node::Class(ref class, ci) => {
+                self.push(Inst::Class(class.clone(), ci));
+            },
+            Node::Look(look) => { self.push(Inst::Look(look)); },
+            Node::Group(ref inner, None) => self.node(inner),
+            Node::Group(ref inner, Some(index)) => {
+                self.push(Inst::Save(index * 2));
+                self.node(inner);
+                self.push(Inst::Save(index * 2 + 1));
+            },
+            Node::Concat(ref items) => {
+                for item in items {
+                    self.node(item);
+                }
+            },
+            Node::Alternate(ref branches) => {
+                let mut jumps = Vec::new();
+                for (i, branch) in branches.iter().enumerate() {
+                    if i + 1 < branches.len() {
+                        let split = self.push(Inst::Split(0, 0));
+                        self.node(branch);
+                        jumps.push(self.push(Inst::Jump(0)));
+                        let next = self.prog.len();
+                        self.prog[split] = Inst::Split(split + 1, next);
+                    } else {
+                        self.node(branch);
+                    }
+                }
+                let end = self.prog.len();
+                for jump in jumps {
+                    self.prog[jump] = Inst::Jump(end);
+                }
+            },
+            Node::Repeat { ref node, min, max, greedy } => {
+                for _ in 0..min {
+                    self.node(node);
+                }
+                match max {
+                    None => self.star(node, greedy),
+                    Some(max) => {
+                        // Each optional copy may skip straight to the end.
+                        let mut splits = Vec::new();
+                        for _ in min..max {
+                            splits.push(self.push(Inst::Split(0, 0)));
+                            self.node(node);
+                        }
+                        let end = self.prog.len();
+                        for split in splits {
+                            self.prog[split] = self.split(split + 1, end, greedy);
+                        }
+                    },
+                }
+            },
+        }
+    }
+
+    fn star(&mut self, node: &Node, greedy: bool) {
+        let split = self.push(Inst::Split(0, 0));
+        self.node(node);
+        self.push(Inst::Jump(split));
+        let end = self.prog.len();
+        self.prog[split] = self.split(split + 1, end, greedy);
+    }
+
+    fn split(&self, body: usize, end: usize, greedy: bool) -> Inst {
+        if greedy { Inst::Split(body, end) } else { Inst::Split(end, body) }
+    }
+}
+
+// ---------------------------------------------------------------------
+// Pike VM
+
+/// Decode the character at `i`. Invalid UTF-8 decodes as `None`, one byte
+/// at a time.
+fn decode(text: &[u8], i: usize) -> (Option<char>, usize) {
+    let width = match text[i] {
+        0x00..=0x7F => return (Some(text[i] as char), 1),
+        0xC0..=0xDF => 2,
+        0xE0..=0xEF => 3,
+        0xF0..=0xF7 => 4,
+        _ => return (None, 1),
+    };
+    if i + width > text.len() {
+        return (None, 1);
+    }
+    match str::from_utf8(&text[i..i + width]) {
+        Ok(s) => (s.chars().next(), width),
+        Err(_) => (None, 1),
+    }
+}
+
+/// Decode the character that ends just before `i`.
+fn decode_last(text: &[u8], i: usize) -> Option<char> {
+    let mut start = i - 1;
+    while start > 0 && i - start < 4 && text[start] & 0xC0 == 0x80 {
+        start -= 1;
+    }
+    match decode(text, start) {
+        (c, width) if start + width == i => c,
+        _ => None,
+    }
+}
+
+fn is_word_char(c: Option<char>) -> bool {
+    c.is_some_and(|c| c.is_alphanumeric() || c == '_')
+}
+
+fn perl_matches(perl: Perl, c: char) -> bool {
+    match perl {
+        Perl::Digit => c.is_ascii_digit(),
+        Perl::Word => c.is_alphanumeric() || c == '_',
+        Perl::Space => c.is_whitespace(),
+    }
+}
+
+fn posix_matches(posix: Posix, c: char) -> bool {
+    match posix {
+        Posix::Alnum => c.is_ascii_alphanumeric(),
+        Posix::Alpha => c.is_ascii_alphabetic(),
+        Posix::Digit => c.is_ascii_digit(),
+        Posix::Lower => c.is_ascii_lowercase(),
+        Posix::Punct => c.is_ascii_punctuation(),
+        Posix::Space => c.is_ascii_whitespace() || c == '\x0B',
+        Posix::Upper => c.is_ascii_uppercase(),
+        Posix::Word => c.is_ascii_alphanumeric() || c == '_',
+        Posix::XDigit => c.is_ascii_hexdigit(),
+    }
+}
+
+/// Map `c` through a case conversion, unless it maps to several chars.
+fn single(c: char, mut mapped: impl Iterator<Item = char>) -> char {
+    match (mapped.next(), mapped.next()) {
+        (Some(m), None) => m,
+        _ => c,
+    }
+}
+
+fn simple_lower(c: char) -> char {
+    single(c, c.to_lowercase())
+}
+
+fn simple_upper(c: char) -> char {
+    single(c, c.to_uppercase())
+}
+
+impl Class {
+    fn matches_exactly(&self, c: char) -> bool {
+        self.items.iter().any(|item| match *item {
+            ClassItem::Range(low, high) => low <= c && c <= high,
+            ClassItem::Perl(perl, negated) => perl_matches(perl, c) != negated,
+            ClassItem::Posix(posix) => posix_matches(posix, c),
+        })
+    }
+
+    fn matches(&self, c: char, ci: bool) -> bool {
+        let found = if ci {
+            self.matches_exactly(c)
+                || self.matches_exactly(simple_lower(c))
+                || self.matches_exactly(simple_upper(c))
+        } else {
+            self.matches_exactly(c)
+        };
+        found != self.negated
+    }
+}
+
+fn char_matches(want: char, c: char, ci: bool) -> bool {
+    want == c || ci && simple_lower(want) == simple_lower(c)
+}
+
+/// Threads waiting on the same position, in priority order.
+struct Threads {
+    dense: Vec<usize>,
+    sparse: Vec<usize>,
+    slots: Vec<Vec<Option<usize>>>,
+}
+
+impl Threads {
+    fn new(size: usize) -> Threads {
+        Threads { dense: Vec::with_capacity(size), sparse: vec![0; size],
+                  slots: Vec::with_capacity(size) }
+    }
+
+    fn contains(&self, pc: usize) -> bool {
+        let i = self.sparse[pc];
+        i < self.dense.len() && self.dense[i] == pc
+    }
+
+    fn mark(&mut self, pc: usize) {
+        self.sparse[pc] = self.dense.len();
+        self.dense.push(pc);
+        self.slots.push(Vec::new());
+    }
+
+    fn clear(&mut self) {
+        self.dense.clear();
+        self.slots.clear();
+    }
+}
+
+/// Work left for `Vm::add`: an instruction to follow, or a capture slot
+/// to put back once everything after its `Save` has been followed.
+enum Step {
+    Follow(usize),
+    Restore(usize, Option<usize>),
+}
+
+struct Vm<'r, 't> {
+    re: &'r Regex,
+    text: &'t [u8],
+    /// Where matches may start and end.
+    starts: &'r dyn Fn(usize) -> bool,
+    ends: &'r dyn Fn(usize) -> bool,
+    /// Kept between calls to `add`, which are many.
+    stack: Vec<Step>,
+}
+
+impl<'r, 't> Vm<'r, 't> {
+    fn new(re: &'r Regex, text: &'t [u8], starts: &'r dyn Fn(usize) -> bool,
+           ends: &'r dyn Fn(usize) -> bool) -> Vm<'r, 't> {
+        Vm { re, text, starts, ends, stack: Vec::new() }
+    }
+
+    fn look(&self, look: Look, pos: usize) -> bool {
+        let text = self.text;
+        match look {
+            Look::StartText => pos == 0,
+            Look::EndText => pos == text.len(),
+            Look::StartLine => pos == 0 || text[pos - 1] == b'\n',
+            // Lines may end in "\r\n", as minigrep's lines may.
+            Look::EndLine => pos == text.len() || text[pos] == b'\n'
+                || text[pos..].starts_with(b"\r\n"),
+            Look::WordBoundary | Look::NotWordBoundary => {
+                let before = pos > 0 && is_word_char(decode_last(text, pos));
+                let after = pos < text.len() && is_word_char(decode(text, pos).0);
+                (before != after) == (look == Look::WordBoundary)
+            },
+        }
+    }
+
+    /// Follow empty transitions from `pc` and queue the resulting threads.
+    ///
+    /// The transitions are followed depth first, in priority order, on a
+    /// stack of their own: a long chain of them would overflow the call
+    /// stack.
+    fn add(&mut self, list: &mut Threads, pc: usize, pos: usize,
+           slots: &mut [Option<usize>]) {
+        let mut stack = mem::take(&mut self.stack);
+        stack.push(Step::Follow(pc));
+        while let Some(step) = stack.pop() {
+            let pc = match step {
+                Step::Follow(pc) => pc,
+                Step::Restore(slot, old) => {
+                    slots[slot] = old;
+                    continue;
+                },
+            };
+            if list.contains(pc) {
+                continue;
+            }
+            list.mark(pc);
+            match self.re.prog[pc] {
+                Inst::Jump(to) => stack.push(Step::Follow(to)),
+                Inst::Split(first, second) => {
+                    stack.push(Step::Follow(second));
+                    stack.push(Step::Follow(first));
+                },
+                Inst::Save(slot) => {
+                    stack.push(Step::Restore(slot, slots[slot]));
+                    slots[slot] = Some(pos);
+                    stack.push(Step::Follow(pc + 1));
+                },
+                Inst::Look(look) => {
+                    if self.look(look, pos) {
+                        stack.push(Step::Follow(pc + 1));
+                    }
+                },
+                _ => {
+                    let i = list.sparse[pc];
+                    list.slots[i] = slots.to_vec();
+                },
+            }
+        }
+        self.stack = stack;
+    }
+
+    fn run(&mut self, start: usize) -> Option<Vec<Option<usize>>> {
+        let re = self.re;
+        let prog = &re.prog;
+        let nslots = self.re.names.len() * 2;
+        let mut current = Threads::new(prog.len());
+        let mut next = Threads::new(prog.len());
+        let mut matched = None;
+        let mut pos = start;
+
+        loop {
+            if matched.is_none() && (self.starts)(pos) {
+                let mut slots = vec![None; nslots];
+                self.add(&mut current, 0, pos, &mut slots);
+            }
+            if current.dense.is_empty() && matched.is_some() {
+                break;
+            }
+
+            let (c, width) = if pos < self.text.len() {
+                decode(self.text, pos)
+            } else {
+                (None, 0)
+            };
+            for i in 0..current.dense.len() {
+                let pc = current.dense[i];
+                let step = match prog[pc] {
+                    // A match that ends in the wrong place lets lower
+                    // priority threads go on.
+                    Inst::Match if !(self.ends)(pos) => false,
+                    Inst::Match => {
+                        matched = Some(current.slots[i].clone());
+                        // Lower priority threads can never win now.
+                        break;
+                    },
+                    Inst::Char(want, ci) => c.is_some_and(
+                        |c| char_matches(want, c, ci)),
+                    Inst::Any(dot_all) => width > 0
+                        && (dot_all || c != Some('\n')),
+                    Inst::Class(ref class, ci) => c.is_some_and(
+                        |c| class.matches(c, ci)),
+                    _ => false,
+                };
+                if step {
+                    let mut slots = current.slots[i].clone();
+                    self.add(&mut next, pc + 1, pos + width, &mut slots);
+                }
+            }
+
+            if pos >= self.text.len() {
+                break;
+            }
+            mem::swap(&mut current, &mut next);
+            next.clear();
+            pos += width;
+        }
+        matched
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn find(pattern: &str, text: &str) -> Option<(usize, usize)> {
+        Regex::new(pattern).unwrap().find(text)
+    }
+
+    #[test]
+    fn literal_and_classes() {
+        assert_eq!(Some((6, 15)), find(r"ERROR \d{3}", "line: ERROR 404 here"));
+        assert_eq!(None, find(r"ERROR \d{3}", "ERROR 4x4"));
+        assert_eq!(Some((0, 3)), find("[^a-c]+", "xyzabc"));
+        assert_eq!(Some((1, 4)), find(r"[[:digit:]_]+", "a1_2b"));
+    }
+
+    #[test]
+    fn alternation_and_anchors() {
+        assert_eq!(Some((4, 7)), find("cat|dog", "hot dog"));
+        assert_eq!(None, find("^dog", "hot dog"));
+        assert_eq!(Some((4, 7)), find("dog$", "hot dog"));
+        assert_eq!(Some((5, 7)), find(r"\bis\b", "this is it"));
+    }
+
+    #[test]
+    fn multi_line_anchors() {
+        let re = Regex::new_multi_line("^b$", false).unwrap();
+        assert_eq!(Some((2, 3)), re.find_at(b"a\nb\nc", 0));
+        assert_eq!(Some((3, 4)), re.find_at(b"a\r\nb\r\nc", 0));
+        assert_eq!(None, Regex::new("^b$").unwrap().find_at(b"a\nb\nc", 0));
+        let re = Regex::new_multi_line("^B", true).unwrap();
+        assert_eq!(Some((2, 3)), re.find_at(b"a\nb", 0));
+    }
+
+    #[test]
+    fn repetition_is_greedy_or_lazy() {
+        assert_eq!(Some((0, 5)), find("a.*b", "axbxb"));
+        assert_eq!(Some((0, 3)), find("a.*?b", "axbxb"));
+        assert_eq!(Some((0, 4)), find("a{2,4}", "aaaaa"));
+    }
+
+    #[test]
+    fn capture_groups() {
+        let re = Regex::new(r"fn (?P<name>\w+)\((\w*)\)").unwrap();
+        let caps = re.captures("pub fn search(query) {").unwrap();
+        assert_eq!(3, caps.len());
+        assert_eq!(Some((4, 20)), caps.get(0));
+        assert_eq!(Some((7, 13)), caps.name("name"));
+        assert_eq!(Some((14, 19)), caps.get(2));
+    }
+
+    #[test]
+    fn case_insensitive() {
+        assert!(Regex::new("(?i)rust").unwrap().is_match("Trust me."));
+        assert!(Regex::new_case_insensitive("RUST").unwrap().is_match("rust"));
+        assert!(!Regex::new("rust").unwrap().is_match("RUST"));
+        let scoped = Regex::new("(?i:a)b").unwrap();
+        assert!(scoped.is_match("Ab"));
+        assert!(!scoped.is_match("aB"));
+        assert!(!scoped.is_match("AB"));
+        assert!(Regex::new("(?:(?i)a)b").unwrap().is_match("Ab"));
+    }
+
+    #[test]
+    fn unicode_and_invalid_bytes() {
+        assert_eq!(Some((0, 4)), find("é.", "éàb"));
+        let re = Regex::new("a.b").unwrap();
+        assert_eq!(Some((0, 3)), re.find_at(b"a\xFFb", 0));
+    }
+
+    #[test]
+    fn long_chains_of_empty_transitions() {
+        // Every `a?` can be skipped, so adding the first thread follows
+        // some 40,000 empty transitions in a row.
+        let re = Regex::new("(?:(?:a?){1000}){40}b").unwrap();
+        assert_eq!(Some((0, 3)), re.find("aab"));
+        let deep = format!("{}a{}", "(".repeat(20_000), ")".repeat(20_000));
+        assert_eq!("groups are nested too deeply",
+                   Regex::new(&deep).unwrap_err().message);
+    }
+
+    #[test]
+    fn matches_that_start_and_end_where_allowed() {
+        let span = |pattern: &str, text: &str, start: usize, end: usize| {
+            Regex::new(pattern).unwrap()
+                .captures_where(text.as_bytes(), 0, &|s| s >= start, &|e| e == end)
+                .and_then(|caps| caps.get(0))
+        };
+        assert_eq!(Some((0, 2)), span("a|ab", "ab", 0, 2));
+        assert_eq!(Some((2, 4)), span("a+", "aaaa", 2, 4));
+        assert_eq!(Some((3, 3)), span("x*", "abc", 0, 3));
+        assert_eq!(None, span("b", "abc", 0, 3));
+    }
+
+    #[test]
+    fn compile_errors() {
+        let err = Regex::new("a(b").unwrap_err();
+        assert_eq!(1, err.position);
+        assert_eq!("unclosed group", err.message);
+        assert_eq!("unmatched ')'", Regex::new("ab)").unwrap_err().message);
+        assert_eq!("invalid character class range z-a",
+                   Regex::new("[z-a]").unwrap_err().message);
+        assert_eq!("repetition operator missing expression",
+                   Regex::new("*a").unwrap_err().message);
+        assert_eq!("unrecognized escape sequence '\\q'",
+                   Regex::new(r"\q").unwrap_err().message);
+    }
+}
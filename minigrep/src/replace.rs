@@ -0,0 +1,378 @@
+//! Replacing matches, for bulk edits.
+//!
+//! Each match on a line is replaced by expanding a template, which can refer
+//! to the regular expression's capture groups.  The changed lines can be
+//! shown as a unified diff, or written back to the file.  Files are written
+//! by renaming a new file over the old one, so a failure part way through
+//! leaves the original as it was.
+
+use std::borrow::Cow;
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+use std::process;
+
+use matcher::Matcher;
+use regex::Captures;
+use Match;
+
+/// Lines of context around each change in a diff.
+const DIFF_CONTEXT: usize = 3;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Piece {
+    Text(String),
+    Group(usize),
+    Name(String),
+}
+
+/// A replacement, with `$1`, `${1}` or `$name` for capture groups, `$0`
+/// for the whole match, and `$$` for `$`.
+///
+/// A reference to a group that did not take part in the match is empty.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    pieces: Vec<Piece>,
+}
+
+impl Template {
+    pub fn new(template: &str) -> Template {
+        let mut pieces = Vec::new();
+        let mut text = String::new();
+        let mut rest = template;
+        while let Some(i) = rest.find('$') {
+            text.push_str(&rest[..i]);
+            rest = &rest[i + 1..];
+            let (reference, len) = if rest.starts_with('$') {
+                (None, 1)
+            } else if let Some(braced) = rest.strip_prefix('{') {
+                match braced.find('}') {
+                    Some(end) => (Some(&braced[..end]), end + 2),
+                    None => (None, 0),
+                }
+            } else {
+                let end = rest.find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                    .unwrap_or(rest.len());
+                (Some(&rest[..end]).filter(|r| !r.is_empty()), end)
+            };
+            match reference {
+                Some(reference) => {
+                    if !text.is_empty() {
+                        pieces.push(Piece::Text(text.split_off(0)));
+                    }
+                    pieces.push(match reference.parse() {
+                        Ok(group) => Piece::Group(group),
+                        Err(_) => Piece::Name(reference.to_string()),
+                    });
+                },
+                // `$$`, or a `$` that refers to nothing.
+                None => text.push('$'),
+            }
+            rest = &rest[len..];
+        }
+        text.push_str(rest);
+        if !text.is_empty() {
+            pieces.push(Piece::Text(text));
+        }
+        Template { pieces }
+    }
+
+    /// Expand the template for the match `start..end` in `line`, with
+    /// `captures` if the match came from a regular expression.  What the
+    /// groups matched is copied from `line` as it is, UTF-8 or not.
+    pub fn expand(&self, line: &[u8], start: usize, end: usize,
+                  captures: Option<&Captures>, out: &mut Vec<u8>) {
+        for piece in &self.pieces {
+            let span = match *piece {
+                Piece::Text(ref text) => {
+                    out.extend_from_slice(text.as_bytes());
+                    continue;
+                },
+                Piece::Group(0) => Some((start, end)),
+                Piece::Group(i) => captures.and_then(|c| c.get(i)),
+                Piece::Name(ref name) => captures.and_then(|c| c.name(name)),
+            };
+            if let Some((start, end)) = span {
+                out.extend_from_slice(&line[start..end]);
+            }
+        }
+    }
+
+    /// `expand`, with the captures `matcher` finds for the match, if it
+    /// finds any; for matchers without them only `$0` refers to anything.
+    pub fn expand_match<M>(&self, matcher: &M, line: &[u8], start: usize,
+                           end: usize, out: &mut Vec<u8>)
+        where M: Matcher + ?Sized
+    {
+        let captures = matcher.captures_at(line, start)
+            .filter(|c| c.get(0) == Some((start, end)));
+        self.expand(line, start, end, captures.as_ref(), out);
+    }
+}
+
+/// A line changed by replacing its matches.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change<'a> {
+    pub line_number: usize,
+    pub old: Cow<'a, str>,
+    pub new: String,
+}
+
+/// Replace every span of `matches` using `template`.
+///
+/// Captures come from `matcher`.  Lines the replacement leaves as they were are not
+/// changes.  The matches are of a file's text, so their lines are UTF-8.
+pub fn changes<'a, M>(matches: &[Match<'a>], matcher: &M, template: &Template)
+    -> Vec<Change<'a>>
+    where M: Matcher + ?Sized
+{
+    matches.iter()
+        .filter_map(|m| {
+            let mut new = Vec::new();
+            let mut last = 0;
+            for &(start, end) in &m.spans {
+                new.extend_from_slice(&m.line[last..start]);
+                template.expand_match(matcher, &m.line, start, end, &mut new);
+                last = end;
+            }
+            new.extend_from_slice(&m.line[last..]);
+            if new == *m.line {
+                return None;
+            }
+            let old = match m.line {
+                Cow::Borrowed(line) => String::from_utf8_lossy(line),
+                Cow::Owned(ref line) => {
+                    Cow::Owned(String::from_utf8_lossy(line).into_owned())
+                },
+            };
+            let new = String::from_utf8_lossy(&new).into_owned();
+            Some(Change { line_number: m.line_number, old, new })
+        })
+        .collect()
+}
+
+/// `contents` with `changes` made, keeping each line's terminator.
+pub fn apply(contents: &str, changes: &[Change]) -> String {
+    let mut out = String::with_capacity(contents.len());
+    let mut changes = changes.iter().peekable();
+    for (i, line) in contents.split_inclusive('\n').enumerate() {
+        match changes.peek() {
+            Some(change) if change.line_number == i + 1 => {
+                out.push_str(&change.new);
+                out.push_str(&line[change.old.len()..]);
+                changes.next();
+            },
+            _ => out.push_str(line),
+        }
+    }
+    out
+}
+
+/// A unified diff of `changes` to `contents`, the file at `path`.
+///
+/// Lines keep their own terminators, so the diff applies as it is to a
+/// file with `\r\n` line endings, or without a final one.
+pub fn diff(path: &str, contents: &str, changes: &[Change]) -> String {
+    let lines: Vec<&str> = contents.split_inclusive('\n').collect();
+    let path = path.strip_prefix("./").unwrap_or(path);
+    let mut out = format!("--- a/{}\n+++ b/{}\n", path, path);
+    // Lines added so far, less those removed, to number the new file.
+    let mut shift: isize = 0;
+    let mut i = 0;
+    while i < changes.len() {
+        // Changes whose contexts touch go in one hunk.
+        let mut j = i + 1;
+        while j < changes.len()
+            && changes[j].line_number - changes[j - 1].line_number
+                <= 2 * DIFF_CONTEXT + 1 {
+            j += 1;
+        }
+        let first = changes[i].line_number.saturating_sub(DIFF_CONTEXT).max(1);
+        let last = (changes[j - 1].line_number + DIFF_CONTEXT).min(lines.len());
+
+        let mut body = String::new();
+        let mut added = 0;
+        let mut hunk = changes[i..j].iter().peekable();
+        for number in first..last + 1 {
+            match hunk.peek() {
+                Some(change) if change.line_number == number => {
+                    let line = lines[number - 1];
+                    push_line(&mut body, '-', line);
+                    let new = format!("{}{}", change.new, &line[change.old.len()..]);
+                    for new in new.split_inclusive('\n') {
+                        push_line(&mut body, '+', new);
+                        added += 1;
+                    }
+                    hunk.next();
+                },
+                _ => {
+                    push_line(&mut body, ' ', lines[number - 1]);
+                    added += 1;
+                },
+            }
+        }
+        let old_len = last + 1 - first;
+        out.push_str(&format!("@@ -{},{} +{},{} @@\n", first, old_len,
+                              first as isize + shift, added));
+        out.push_str(&body);
+        shift += added as isize - old_len as isize;
+        i = j;
+    }
+    out
+}
+
+/// Add `line` to the `body` of a hunk, marked with `sign`, and say so if
+/// it is the last and has no terminator.
+fn push_line(body: &mut String, sign: char, line: &str) {
+    body.push(sign);
+    body.push_str(line);
+    if !line.ends_with('\n') {
+        body.push_str("\n\\ No newline at end of file\n");
+    }
+}
+
+/// Replace the file at `path` with `contents`, keeping its permissions.
+///
+/// The new contents go to a temporary file beside it, which is then renamed
+/// over it, so the file is never left half written.  A symbolic link is
+/// followed, and the file it points to replaced.
+pub fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let path = &fs::canonicalize(path)?;
+    let permissions = fs::metadata(path)?.permissions();
+    let name = path.file_name().map_or_else(Default::default,
+                                            |n| n.to_string_lossy());
+    let temp = path.with_file_name(
+        format!(".{}.minigrep-{}", name, process::id()));
+    let result = fs::File::create(&temp)
+        .and_then(|mut file| {
+            file.write_all(contents)?;
+            file.sync_all()
+        })
+        .and_then(|_| fs::set_permissions(&temp, permissions))
+        .and_then(|_| fs::rename(&temp, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+    use matcher::{self, Regexes};
+    use regex::Regex;
+
+    fn expand(template: &str, pattern: &str, line: &str) -> String {
+        let re = Regex::new(pattern).unwrap();
+        let captures = re.captures(line).unwrap();
+        let (start, end) = captures.get(0).unwrap();
+        let mut out = Vec::new();
+        Template::new(template).expand(line.as_bytes(), start, end, Some(&captures),
+                                       &mut out);
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn templates() {
+        assert_eq!("b-a", expand("$2-$1", "(a)(b)", "ab"));
+        assert_eq!("[ab]", expand("[${0}]", "ab", "ab"));
+        assert_eq!("b!", expand("${second}!", "(a)(?P<second>b)", "ab"));
+        assert_eq!("$1 $", expand("$$1 $", "a", "a"));
+        assert_eq!("", expand("$3", "a", "a"));
+        let mut out = Vec::new();
+        Template::new("<$0>").expand(b"a\xFFb", 1, 2, None, &mut out);
+        assert_eq!(b"<\xFF>".to_vec(), out);
+    }
+
+    static CONTENTS: &str = "\
+one
+two
+fn old_name() {}
+three
+four
+five
+six
+seven
+eight
+nine
+old_name();
+";
+
+    fn replaced(pattern: &str, template: &str) -> (Vec<Change<'static>>, String) {
+        let re = Regexes::new(&[pattern], false).unwrap();
+        let matches = matcher::search(&re, CONTENTS.as_bytes());
+        let changes = changes(&matches, &re, &Template::new(template));
+        let new = apply(CONTENTS, &changes);
+        (changes, new)
+    }
+
+    #[test]
+    fn replaces_and_keeps_terminators() {
+        let (changes, new) = replaced(r"old_(\w+)", "new_$1");
+        assert_eq!(vec![3, 11], changes.iter().map(|c| c.line_number)
+                                       .collect::<Vec<_>>());
+        assert_eq!(CONTENTS.replace("old_name", "new_name"), new);
+        assert!(replaced("one", "one").0.is_empty());
+    }
+
+    #[test]
+    fn unified_diff() {
+        let (changes, _) = replaced(r"old_(\w+)", "new_$1");
+        assert_eq!("\
+--- a/f.rs
++++ b/f.rs
+@@ -1,6 +1,6 @@
+ one
+ two
+-fn old_name() {}
++fn new_name() {}
+ three
+ four
+ five
+@@ -8,4 +8,4 @@
+ seven
+ eight
+ nine
+-old_name();
++new_name();
+", diff("f.rs", CONTENTS, &changes));
+
+        let (changes, _) = replaced("two", "2\n2");
+        assert!(diff("f", CONTENTS, &changes).starts_with(
+            "--- a/f\n+++ b/f\n@@ -1,5 +1,6 @@\n one\n-two\n+2\n+2\n"));
+
+        // Each line keeps its own terminator, or says it has none.
+        let contents = "one\r\ntwo\r\nthree";
+        let re = Regexes::new(&["two|three"], false).unwrap();
+        let matches = matcher::search(&re, contents.as_bytes());
+        let changes = super::changes(&matches, &re, &Template::new("<$0>"));
+        assert_eq!("\
+--- a/f
++++ b/f
+@@ -1,3 +1,3 @@
+ one\r
+-two\r
++<two>\r
+-three
+\\ No newline at end of file
++<three>
+\\ No newline at end of file
+", diff("f", contents, &changes));
+    }
+
+    #[test]
+    fn writes_atomically() {
+        let path = env::temp_dir().join(format!("minigrep-replace-{}",
+                                                process::id()));
+        fs::write(&path, "old").unwrap();
+        write_atomically(&path, b"new").unwrap();
+        assert_eq!("new", fs::read_to_string(&path).unwrap());
+        assert_eq!(1, fs::read_dir(env::temp_dir()).unwrap()
+            .filter(|e| e.as_ref().unwrap().file_name().to_string_lossy()
+                    .contains(&format!("minigrep-replace-{}", process::id())))
+            .count());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,649 @@
+//! Searching any `BufRead` without reading it all into memory.
+//!
+//! Input is taken in chunks of whole lines, as much as the reader has ready,
+//! so results come out as soon as the lines arrive.  Each chunk is searched
+//! a line at a time with the `Matcher`, and context lines are tracked across
+//! chunks.
+//!
+//! Input need not be UTF-8: lines are searched and reported as the bytes
+//! they are, so every position is an offset into the input.  A chunk with a
+//! NUL byte marks the input as binary, and what happens then is up to
+//! `Binary`.  A slice is cut into chunks where reading it through a buffer
+//! of `CHUNK` bytes would cut it, so a file mapped into memory is found to
+//! be binary just as it is when read.
+
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::io;
+use std::io::prelude::*;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use context::Line;
+use matcher::{self, Matcher};
+use memchr;
+use {Binary, Match};
+
+/// Size of the buffer files are read through, and of the pieces a slice
+/// is searched in.
+pub const CHUNK: usize = 64 * 1024;
+
+/// What a search reports to its sink.
+#[derive(Debug, PartialEq)]
+pub enum Output<'a> {
+    /// A new hunk starts; the lines before it were not printed.
+    Break,
+    /// A matching line, or a line of context.
+    Line(Line<'a>),
+    /// The input is binary and has a match; nothing more will be reported.
+    Binary,
+}
+
+/// Context lines waiting for a match, and where the last output ended.
+struct State {
+    before: usize,
+    after: usize,
+    buffered: VecDeque<(usize, usize, Vec<u8>)>,
+    after_left: usize,
+    last_printed: Option<usize>,
+    matches: usize,
+}
+
+impl State {
+    fn line<S>(&mut self, line_number: usize, offset: usize, line: &[u8],
+               matched: Option<Match>, sink: &mut S) -> io::Result<()>
+        where S: FnMut(Output) -> io::Result<()>
+    {
+        if let Some(m) = matched {
+            let first = self.buffered.front().map_or(line_number, |b| b.0);
+            if self.last_printed.is_none_or(|last| first > last + 1) {
+                sink(Output::Break)?;
+            }
+            for (number, offset, text) in self.buffered.drain(..) {
+                sink(Output::Line(Line { line_number: number, offset,
+                                         line: &text, matched: None }))?;
+            }
+            sink(Output::Line(Line { line_number, offset, line,
+                                     matched: Some(m) }))?;
+            self.matches += 1;
+            self.last_printed = Some(line_number);
+            self.after_left = self.after;
+        } else if self.after_left > 0 {
+            sink(Output::Line(Line { line_number, offset, line,
+                                     matched: None }))?;
+            self.after_left -= 1;
+            self.last_printed = Some(line_number);
+        } else if self.before > 0 {
+            if self.buffered.len() == self.before {
+                self.buffered.pop_front();
+            }
+            self.buffered.push_back((line_number, offset, line.to_vec()));
+        }
+        Ok(())
+    }
+}
+
+/// Searches readers one chunk of lines at a time.
+pub struct Searcher<'m> {
+    pub matcher: &'m dyn Matcher,
+    /// Lines of context to report before each match.
+    pub before: usize,
+    /// Lines of context to report after each match.
+    pub after: usize,
+    /// Report the lines that do not match instead.
+    pub invert: bool,
+    /// Search the whole input at once, so that matches may span lines.
+    pub multiline: bool,
+    /// Stop reading after this many matching lines.
+    pub max_count: Option<usize>,
+    /// Matching lines left for this search and any others sharing it;
+    /// reading stops once it is spent.
+    pub budget: Option<&'m Budget>,
+    /// What to do with binary input.
+    pub binary: Binary,
+}
+
+impl<'m> Searcher<'m> {
+    /// A searcher reporting every line `matcher` matches.
+    pub fn new(matcher: &'m dyn Matcher) -> Searcher<'m> {
+        Searcher { matcher, before: 0, after: 0, invert: false,
+                   multiline: false, max_count: None, budget: None,
+                   binary: Binary::Report }
+    }
+
+    /// Search `reader`, passing each matching line and the context around
+    /// it to `sink`.
+    ///
+    /// Returns the number of matching lines.
+    pub fn search<R, S>(&self, mut reader: R, mut sink: S) -> io::Result<usize>
+        where R: BufRead, S: FnMut(Output) -> io::Result<()>
+    {
+        if self.multiline {
+            let mut input = Vec::new();
+            reader.read_to_end(&mut input)?;
+            return self.search_slice(&input, sink);
+        }
+        let mut progress = Progress::new(self);
+        let mut chunk: Vec<u8> = Vec::new();
+        loop {
+            // Take everything the reader has up to its last complete line.
+            let (used, complete) = {
+                let buf = reader.fill_buf()?;
+                match memchr::memrchr(b'\n', buf) {
+                    Some(i) => {
+                        chunk.extend_from_slice(&buf[..i + 1]);
+                        (i + 1, true)
+                    },
+                    None => {
+                        chunk.extend_from_slice(buf);
+                        (buf.len(), false)
+                    },
+                }
+            };
+            reader.consume(used);
+            let eof = used == 0;
+            if !complete && !eof {
+                continue;
+            }
+            if chunk.is_empty() {
+                break;
+            }
+            if let Some(count) = self.chunk(&mut progress, &chunk, eof, &mut sink)? {
+                return Ok(count);
+            }
+            chunk.clear();
+            if eof {
+                break;
+            }
+        }
+        Ok(progress.state.matches)
+    }
+
+    /// Search `input` without copying it, as when it is a memory map, in
+    /// the chunks reading it through a buffer of `CHUNK` bytes would give.
+    ///
+    /// Returns the number of matching lines.
+    pub fn search_slice<S>(&self, input: &[u8], mut sink: S) -> io::Result<usize>
+        where S: FnMut(Output) -> io::Result<()>
+    {
+        let mut progress = Progress::new(self);
+        if self.multiline {
+            if input.is_empty() {
+                return Ok(0);
+            }
+            let done = self.chunk(&mut progress, input, true, &mut sink)?;
+            return Ok(done.unwrap_or(progress.state.matches));
+        }
+        // Each buffer read ends a chunk at its last terminator, if it has
+        // one; the input's last line, if unterminated, is a chunk of its own.
+        let (mut start, mut read) = (0, 0);
+        while start < input.len() {
+            read = (read + CHUNK).min(input.len());
+            let end = match memchr::memrchr(b'\n', &input[start..read]) {
+                Some(i) => start + i + 1,
+                None if read == input.len() => read,
+                None => continue,
+            };
+            let last = end == input.len();
+            if let Some(count) = self.chunk(&mut progress, &input[start..end], last,
+                                            &mut sink)? {
+                return Ok(count);
+            }
+            start = end;
+        }
+        Ok(progress.state.matches)
+    }
+
+    /// Search a chunk of whole lines, the last perhaps unterminated.  Only
+    /// a chunk that is not the `last` has its lines counted, to number those
+    /// of the next, and then only those the matcher did not reach.
+    ///
+    /// Returns the number of matching lines if the search is over.
+    fn chunk<S>(&self, progress: &mut Progress, chunk: &[u8], last: bool,
+                sink: &mut S) -> io::Result<Option<usize>>
+        where S: FnMut(Output) -> io::Result<()>
+    {
+        if self.binary != Binary::Text && !progress.binary
+            && memchr::memchr(0, chunk).is_some() {
+            if self.binary == Binary::Skip {
+                // Lines before this chunk may have been reported already;
+                // they still count.
+                return Ok(Some(progress.state.matches));
+            }
+            progress.binary = true;
+        }
+        // Matches are found only as they are needed, so a search that
+        // stops early does not look through the rest of the chunk.
+        if self.multiline {
+            // The whole input is one chunk, so this is the last.
+            let spans = self.matcher.find_all(chunk);
+            let matches = matcher::touched_lines(chunk, spans).into_iter();
+            return self.report(progress, chunk, matches, sink);
+        }
+        let mut matches = matcher::matches(self.matcher, chunk);
+        let done = self.report(progress, chunk, &mut matches, sink)?;
+        if done.is_none() && !last {
+            // A chunk that is not the last ends with a terminator.
+            let (at, line_number) = matches.position();
+            progress.line_number += line_number - 1 + memchr::count(b'\n', &chunk[at..]);
+            progress.offset += chunk.len();
+        }
+        Ok(done)
+    }
+
+    /// Report the `matches` in `chunk`, and the lines around them.
+    ///
+    /// Returns the number of matching lines if the search is over.
+    fn report<'t, I, S>(&self, progress: &mut Progress, chunk: &'t [u8],
+                        mut matches: I, sink: &mut S)
+        -> io::Result<Option<usize>>
+        where I: Iterator<Item = Match<'t>>, S: FnMut(Output) -> io::Result<()>
+    {
+        if progress.binary {
+            // Report the first match, not the binary lines themselves.
+            let found = if self.invert {
+                let lines = memchr::count(b'\n', chunk)
+                    + !chunk.ends_with(b"\n") as usize;
+                lines > matches.count()
+            } else {
+                matches.next().is_some()
+            };
+            if found {
+                if self.limited(&progress.state) || !self.take() {
+                    return Ok(Some(progress.state.matches));
+                }
+                sink(Output::Binary)?;
+                return Ok(Some(progress.state.matches + 1));
+            }
+            return Ok(None);
+        }
+        if self.invert {
+            self.every_line(progress, chunk, matches, sink)
+        } else {
+            self.matched_lines(progress, chunk, matches, sink)
+        }
+    }
+
+    /// Whether no more lines may match.
+    fn limited(&self, state: &State) -> bool {
+        self.max_count.is_some_and(|max| state.matches >= max)
+            || self.budget.is_some_and(Budget::is_spent)
+    }
+
+    /// Take a matching line from the budget, if there is one; false if it
+    /// is spent.
+    fn take(&self) -> bool {
+        self.budget.is_none_or(Budget::take)
+    }
+
+    /// Report the `matches` in `text`, and their context, going through
+    /// the lines between them only as far as the context needs.
+    fn matched_lines<'t, I, S>(&self, progress: &mut Progress, text: &'t [u8],
+                               matches: I, sink: &mut S)
+        -> io::Result<Option<usize>>
+        where I: Iterator<Item = Match<'t>>, S: FnMut(Output) -> io::Result<()>
+    {
+        // The first line not yet seen, by where it starts and its index.
+        let mut at = 0;
+        let mut index = 0;
+        for m in matches {
+            if let Some(count) = self.between(progress, text, at, m.offset, index,
+                                              sink)? {
+                return Ok(Some(count));
+            }
+            let (line, next) = line_at(text, m.offset);
+            let matched = if self.limited(&progress.state) || !self.take() {
+                if progress.state.after_left == 0 {
+                    return Ok(Some(progress.state.matches));
+                }
+                None
+            } else {
+                Some(Match {
+                    line_number: progress.line_number + m.line_number - 1,
+                    offset: progress.offset + m.offset,
+                    ..m
+                })
+            };
+            progress.state.line(progress.line_number + m.line_number - 1,
+                                progress.offset + m.offset, line, matched,
+                                sink)?;
+            at = next;
+            index = m.line_number;
+        }
+        self.between(progress, text, at, text.len(), index, sink)
+    }
+
+    /// Go through the lines of `text` from byte `from` up to `to`, the
+    /// first of them at `index` in the chunk, none of which match.  Only
+    /// those that follow a match, and those that may come before the next,
+    /// are looked at.
+    ///
+    /// Returns the number of matching lines if the search is over.
+    fn between<S>(&self, progress: &mut Progress, text: &[u8], mut from: usize,
+                  to: usize, mut index: usize, sink: &mut S)
+        -> io::Result<Option<usize>>
+        where S: FnMut(Output) -> io::Result<()>
+    {
+        let state = &mut progress.state;
+        while from < to && state.after_left > 0 {
+            let (line, next) = line_at(text, from);
+            state.line(progress.line_number + index, progress.offset + from, line,
+                       None, sink)?;
+            from = next;
+            index += 1;
+        }
+        if from < to && self.limited(state) {
+            return Ok(Some(state.matches));
+        }
+        if from >= to || self.before == 0 {
+            return Ok(None);
+        }
+
+        // The starts of the last few lines, found backwards from `to`.
+        let mut starts = Vec::new();
+        let mut end = to;
+        while starts.len() < self.before && end > from {
+            // Leave out the terminator of the line before `end`, if any.
+            let last = end - (text[end - 1] == b'\n') as usize;
+            end = memchr::memrchr(b'\n', &text[from..last])
+                .map_or(from, |i| from + i + 1);
+            starts.push(end);
+        }
+        index += memchr::count(b'\n', &text[from..end]);
+        for &start in starts.iter().rev() {
+            let (line, _) = line_at(text, start);
+            state.line(progress.line_number + index, progress.offset + start, line,
+                       None, sink)?;
+            index += 1;
+        }
+        Ok(None)
+    }
+
+    /// Report every line of `text`, given the lines that match, for a
+    /// search that reports the lines that do not.
+    fn every_line<'t, I, S>(&self, progress: &mut Progress, text: &'t [u8],
+                            matches: I, sink: &mut S)
+        -> io::Result<Option<usize>>
+        where I: Iterator<Item = Match<'t>>, S: FnMut(Output) -> io::Result<()>
+    {
+        let (line_number, offset) = (progress.line_number, progress.offset);
+        let state = &mut progress.state;
+        let mut matches = matches.peekable();
+        let (mut at, mut i) = (0, 0);
+        while at < text.len() {
+            let (line, next) = line_at(text, at);
+            let found = match matches.peek() {
+                Some(m) if m.line_number == i + 1 => matches.next(),
+                _ => None,
+            };
+            let mut matched = match found {
+                Some(_) => None,
+                None => Some(Match {
+                    line_number: line_number + i,
+                    offset: offset + at,
+                    start: 0,
+                    end: 0,
+                    pattern: 0,
+                    distance: 0,
+                    spans: Vec::new(),
+                    line: Cow::Borrowed(line),
+                }),
+            };
+
+            // Past the limit, lines are only good for trailing context.
+            if self.limited(state) || (matched.is_some() && !self.take()) {
+                if state.after_left == 0 {
+                    return Ok(Some(state.matches));
+                }
+                matched = None;
+            }
+            state.line(line_number + i, offset + at, line, matched, sink)?;
+            at = next;
+            i += 1;
+        }
+        Ok(None)
+    }
+}
+
+/// A number of matching lines shared by many searches, such as those of
+/// every file of a search run on several threads.
+#[derive(Debug)]
+pub struct Budget {
+    left: AtomicUsize,
+}
+
+impl Budget {
+    pub fn new(lines: usize) -> Budget {
+        Budget { left: AtomicUsize::new(lines) }
+    }
+
+    /// Take one line from the budget, unless it is spent.
+    pub fn take(&self) -> bool {
+        self.left.fetch_update(Ordering::Relaxed, Ordering::Relaxed,
+                               |left| left.checked_sub(1)).is_ok()
+    }
+
+    pub fn is_spent(&self) -> bool {
+        self.left.load(Ordering::Relaxed) == 0
+    }
+}
+
+/// How far a search has got.
+struct Progress {
+    state: State,
+    /// Number of the first line of the next chunk.
+    line_number: usize,
+    /// Offset of the next chunk in the input.
+    offset: usize,
+    /// The input has turned out to be binary.
+    binary: bool,
+}
+
+impl Progress {
+    fn new(searcher: &Searcher) -> Progress {
+        let state = State { before: searcher.before, after: searcher.after,
+                            buffered: VecDeque::new(), after_left: 0,
+                            last_printed: None, matches: 0 };
+        Progress { state, line_number: 1, offset: 0, binary: false }
+    }
+}
+
+/// The line of `text` starting at byte `start`, without its terminator,
+/// and where the next one starts.
+fn line_at(text: &[u8], start: usize) -> (&[u8], usize) {
+    match memchr::memchr(b'\n', &text[start..]) {
+        Some(i) => {
+            let line = &text[start..start + i];
+            (line.strip_suffix(b"\r").unwrap_or(line), start + i + 1)
+        },
+        None => (&text[start..], text.len()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use matcher::Literal;
+
+    /// Render one output as text, with `*` marking matches.
+    fn show(output: Output) -> String {
+        match output {
+            Output::Break => String::from("--"),
+            Output::Binary => String::from("binary"),
+            Output::Line(l) => format!(
+                "{}@{}{}{}", l.line_number, l.offset,
+                if l.matched.is_some() { "*" } else { "-" },
+                String::from_utf8_lossy(l.line)),
+        }
+    }
+
+    /// Render the output of reading `input` through a buffer of `capacity`.
+    fn render(input: &[u8], capacity: usize, before: usize, after: usize)
+        -> Vec<String> {
+        let reader = io::BufReader::with_capacity(capacity, input);
+        let mut lines = Vec::new();
+        let literal = Literal::new("match");
+        let searcher = Searcher { before, after, ..Searcher::new(&literal) };
+        searcher.search(reader, |output| {
+            lines.push(show(output));
+            Ok(())
+        }).unwrap();
+        lines
+    }
+
+    static INPUT: &[u8] = b"one\nmatch two\nthree\nfour\r\nfive\nsix match";
+
+    #[test]
+    fn numbers_lines_across_chunks() {
+        let expected = vec!["--", "2@4*match two", "--", "6@31*six match"];
+        assert_eq!(expected, render(INPUT, 4, 0, 0));
+        assert_eq!(expected, render(INPUT, 1024, 0, 0));
+    }
+
+    #[test]
+    fn context_across_chunks() {
+        let expected = vec!["--", "1@0-one", "2@4*match two", "3@14-three",
+                            "--", "5@26-five", "6@31*six match"];
+        assert_eq!(expected, render(INPUT, 5, 1, 1));
+        assert_eq!(expected, render(INPUT, 1024, 1, 1));
+    }
+
+    #[test]
+    fn slices_search_like_readers() {
+        let literal = Literal::new("match");
+        for &(before, after) in &[(0, 0), (1, 1), (0, 3), (2, 0)] {
+            let searcher = Searcher { before, after, ..Searcher::new(&literal) };
+            let mut lines = Vec::new();
+            let count = searcher.search_slice(INPUT, |output| {
+                lines.push(show(output));
+                Ok(())
+            }).unwrap();
+            assert_eq!(2, count);
+            assert_eq!(render(INPUT, 4, before, after), lines);
+        }
+    }
+
+    #[test]
+    fn multiline_matches_with_context() {
+        let literal = Literal::new("two\nmatch");
+        let searcher = Searcher { multiline: true, before: 1, after: 1,
+                                  ..Searcher::new(&literal) };
+        let input = b"one\ntwo\nmatch\nthree\nfour\nfive";
+        let mut lines = Vec::new();
+        let count = searcher.search(io::BufReader::with_capacity(4, &input[..]),
+                                    |output| {
+            lines.push(show(output));
+            Ok(())
+        }).unwrap();
+        assert_eq!(2, count);
+        assert_eq!(vec!["--", "1@0-one", "2@4*two", "3@8*match", "4@14-three"],
+                   lines);
+    }
+
+    #[test]
+    fn invert_and_max_count() {
+        let reader = io::BufReader::new(INPUT);
+        let literal = Literal::new("match");
+        let searcher = Searcher { invert: true, max_count: Some(2),
+                                  ..Searcher::new(&literal) };
+        let mut lines = Vec::new();
+        let count = searcher.search(reader, |output| {
+            if let Output::Line(l) = output {
+                lines.push(String::from_utf8_lossy(l.line).into_owned());
+            }
+            Ok(())
+        }).unwrap();
+        assert_eq!(2, count);
+        assert_eq!(vec!["one", "three"], lines);
+    }
+
+    #[test]
+    fn budget_is_shared() {
+        let literal = Literal::new("match");
+        let budget = Budget::new(3);
+        let searcher = Searcher { budget: Some(&budget), after: 1,
+                                  ..Searcher::new(&literal) };
+        let mut lines = Vec::new();
+        let mut sink = |output: Output| {
+            lines.push(show(output));
+            Ok(())
+        };
+        assert_eq!(2, searcher.search_slice(INPUT, &mut sink).unwrap());
+        assert_eq!(1, searcher.search_slice(b"a match\nb\nmatch", &mut sink).unwrap());
+        assert!(budget.is_spent());
+        assert_eq!(0, searcher.search_slice(INPUT, &mut sink).unwrap());
+        assert_eq!(vec!["--", "2@4*match two", "3@14-three", "--", "6@31*six match",
+                        "--", "1@0*a match", "2@8-b"], lines);
+    }
+
+    #[test]
+    fn invalid_utf8_is_kept() {
+        let literal = Literal::new("match");
+        let searcher = Searcher::new(&literal);
+        let mut found = Vec::new();
+        searcher.search_slice(b"a\xFF\xFEb\n\xE9 match\xFF\n", |output| {
+            if let Output::Line(l) = output {
+                let m = l.matched.unwrap();
+                found.push((l.line.to_vec(), l.offset, m.start, m.end));
+            }
+            Ok(())
+        }).unwrap();
+        assert_eq!(vec![(b"\xE9 match\xFF".to_vec(), 5, 2, 7)], found);
+    }
+
+    fn binary(input: &[u8], binary: Binary) -> (usize, Vec<String>) {
+        let reader = io::BufReader::new(input);
+        let literal = Literal::new("match");
+        let searcher = Searcher { binary, ..Searcher::new(&literal) };
+        let mut lines = Vec::new();
+        let count = searcher.search(reader, |output| {
+            lines.push(match output {
+                Output::Break => String::from("--"),
+                Output::Binary => String::from("binary"),
+                Output::Line(l) => String::from_utf8_lossy(l.line).into_owned(),
+            });
+            Ok(())
+        }).unwrap();
+        (count, lines)
+    }
+
+    #[test]
+    fn binary_input() {
+        let input = b"one\x00\nmatch\nmatch\n";
+        assert_eq!((1, vec!["binary".to_string()]), binary(input, Binary::Report));
+        assert_eq!((0, Vec::new()), binary(input, Binary::Skip));
+        // A NUL found after lines were reported stops the search, but what
+        // was reported still counts.
+        let mut late = b"match\n".to_vec();
+        late.extend_from_slice(&[b'x'; 10_000]);
+        late.extend_from_slice(b"\n\x00\nmatch\n");
+        assert_eq!((1, vec!["--".to_string(), "match".to_string()]),
+                   binary(&late, Binary::Skip));
+        assert_eq!(2, binary(input, Binary::Text).0);
+        assert_eq!((0, Vec::new()), binary(b"one\x00\n", Binary::Report));
+    }
+
+    #[test]
+    fn binary_input_read_or_mapped() {
+        let literal = Literal::new("match");
+        let mut input = b"match\n".to_vec();
+        input.extend_from_slice(&[b'x'; CHUNK]);
+        input.extend_from_slice(b"\n\x00 match\n");
+        let cases = [(Binary::Report, vec!["--", "1@0*match", "binary"]),
+                     (Binary::Skip, vec!["--", "1@0*match"])];
+        for &(binary, ref expected) in &cases {
+            let searcher = Searcher { binary, ..Searcher::new(&literal) };
+            let (mut read, mut mapped) = (Vec::new(), Vec::new());
+            let reader = io::BufReader::with_capacity(CHUNK, &input[..]);
+            let count = searcher.search(reader, |output| {
+                read.push(show(output));
+                Ok(())
+            }).unwrap();
+            assert_eq!(count, searcher.search_slice(&input, |output| {
+                mapped.push(show(output));
+                Ok(())
+            }).unwrap());
+            assert_eq!(expected, &read);
+            assert_eq!(read, mapped);
+        }
+    }
+}
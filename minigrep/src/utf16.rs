@@ -0,0 +1,132 @@
+//! Reading UTF-16 text as UTF-8.
+//!
+//! Files that start with a UTF-16 byte order mark are searched through a
+//! `Decoder`, so the matchers only ever see UTF-8.  Offsets in the
+//! results then count bytes of the UTF-8 text, not of the file.
+
+use std::char;
+use std::io;
+use std::io::prelude::*;
+
+/// Byte order of UTF-16 text.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Endian {
+    Little,
+    Big,
+}
+
+/// The byte order given by the byte order mark `bytes` start with, if any.
+pub fn bom(bytes: &[u8]) -> Option<Endian> {
+    match bytes {
+        [0xFF, 0xFE, ..] => Some(Endian::Little),
+        [0xFE, 0xFF, ..] => Some(Endian::Big),
+        _ => None,
+    }
+}
+
+/// Reads UTF-16 from `inner` and gives UTF-8.
+///
+/// Unpaired surrogates, and an odd byte at the end, become U+FFFD.
+pub struct Decoder<R> {
+    inner: R,
+    endian: Endian,
+    /// Bytes read but not yet decoded: an odd byte, or a high surrogate
+    /// waiting for its pair.
+    raw: Vec<u8>,
+    /// Decoded text not yet read, from `pos` on.
+    decoded: Vec<u8>,
+    pos: usize,
+}
+
+impl<R: Read> Decoder<R> {
+    /// Decoder for the text after the byte order mark.
+    pub fn new(inner: R, endian: Endian) -> Decoder<R> {
+        Decoder { inner, endian, raw: Vec::new(), decoded: Vec::new(), pos: 0 }
+    }
+
+    /// Decode the next piece of input into `decoded`.
+    ///
+    /// Returns false at the end of the input.
+    fn decode(&mut self) -> io::Result<bool> {
+        let mut buf = [0; 8 * 1024];
+        let n = self.inner.read(&mut buf)?;
+        self.raw.extend_from_slice(&buf[..n]);
+        let eof = n == 0;
+
+        let endian = self.endian;
+        let mut units: Vec<u16> = self.raw.chunks(2)
+            .filter(|pair| pair.len() == 2)
+            .map(|pair| match endian {
+                Endian::Little => u16::from_le_bytes([pair[0], pair[1]]),
+                Endian::Big => u16::from_be_bytes([pair[0], pair[1]]),
+            })
+            .collect();
+        let mut kept = self.raw.len() % 2;
+        if !eof && units.last().is_some_and(|&u| (0xD800..0xDC00).contains(&u)) {
+            units.pop();
+            kept += 2;
+        }
+
+        self.decoded.clear();
+        self.pos = 0;
+        for c in char::decode_utf16(units) {
+            let c = c.unwrap_or(char::REPLACEMENT_CHARACTER);
+            let mut utf8 = [0; 4];
+            self.decoded.extend_from_slice(c.encode_utf8(&mut utf8).as_bytes());
+        }
+        let len = self.raw.len();
+        self.raw.drain(..len - kept);
+        if eof && !self.raw.is_empty() {
+            self.raw.clear();
+            self.decoded.extend_from_slice("\u{FFFD}".as_bytes());
+        }
+        Ok(!eof || !self.decoded.is_empty())
+    }
+}
+
+impl<R: Read> Read for Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.decoded.len() {
+            if !self.decode()? {
+                return Ok(0);
+            }
+        }
+        let n = buf.len().min(self.decoded.len() - self.pos);
+        buf[..n].copy_from_slice(&self.decoded[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn decode(bytes: &[u8], endian: Endian) -> String {
+        let mut text = String::new();
+        Decoder::new(bytes, endian).read_to_string(&mut text).unwrap();
+        text
+    }
+
+    #[test]
+    fn byte_order_marks() {
+        assert_eq!(Some(Endian::Little), bom(b"\xFF\xFEa\x00"));
+        assert_eq!(Some(Endian::Big), bom(b"\xFE\xFF\x00a"));
+        assert_eq!(None, bom(b"ab"));
+        assert_eq!(None, bom(b"\xFF"));
+    }
+
+    #[test]
+    fn decodes_both_orders() {
+        assert_eq!("hé\n", decode(b"h\x00\xE9\x00\n\x00", Endian::Little));
+        assert_eq!("hé\n", decode(b"\x00h\x00\xE9\x00\n", Endian::Big));
+        // A surrogate pair, for U+1F600.
+        assert_eq!("\u{1F600}", decode(b"\x3D\xD8\x00\xDE", Endian::Little));
+    }
+
+    #[test]
+    fn bad_input_is_replaced() {
+        assert_eq!("a\u{FFFD}", decode(b"a\x00b", Endian::Little));
+        assert_eq!("\u{FFFD}a", decode(b"\x3D\xD8a\x00", Endian::Little));
+    }
+}
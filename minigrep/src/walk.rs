@@ -0,0 +1,415 @@
+//! Recursive directory traversal.
+//!
+//! `Walk` turns a list of paths into the files underneath them, in sorted
+//! order.  Paths named by the caller are always followed, even if they are
+//! symbolic links; links found while recursing are followed only on request.
+//!
+//! A `Filter` can leave out some of what is found while recursing: hidden
+//! files, whatever the ignore files in each directory say, and files not
+//! matching the globs given.  Directories named `.git` are always left out,
+//! as they hold git's own files.  Paths named by the caller are never left
+//! out.
+//! The ignore files that count are those in the directories walked and in
+//! those above them, up to the top of the git repository they are in.
+
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use ignore::{Ignore, Rule};
+use Error;
+
+/// File types for `Filter::add_type`, with the globs matching them.
+pub static TYPES: &[(&str, &[&str])] = &[
+    ("c", &["*.c", "*.h"]),
+    ("cpp", &["*.cpp", "*.cc", "*.cxx", "*.hpp", "*.hh", "*.h"]),
+    ("css", &["*.css"]),
+    ("go", &["*.go"]),
+    ("html", &["*.html", "*.htm"]),
+    ("java", &["*.java"]),
+    ("js", &["*.js", "*.mjs"]),
+    ("json", &["*.json"]),
+    ("md", &["*.md", "*.markdown"]),
+    ("py", &["*.py"]),
+    ("rust", &["*.rs"]),
+    ("sh", &["*.sh", "*.bash"]),
+    ("toml", &["*.toml"]),
+    ("ts", &["*.ts", "*.tsx"]),
+    ("txt", &["*.txt"]),
+    ("yaml", &["*.yaml", "*.yml"]),
+];
+
+/// What to leave out of a walk.
+#[derive(Debug, Clone)]
+pub struct Filter {
+    /// Include files and directories whose names start with `.`.
+    pub hidden: bool,
+    /// Leave out what `.gitignore` and `.ignore` files say to.
+    pub ignore_files: bool,
+    /// If any, include only files matching one of these.
+    pub include: Vec<Rule>,
+    /// Leave out files and directories matching any of these.
+    pub exclude: Vec<Rule>,
+    /// Leave out files bigger than this many bytes.
+    pub max_size: Option<u64>,
+}
+
+impl Filter {
+    /// A filter that leaves nothing out but `.git` directories.
+    pub fn none() -> Filter {
+        Filter { hidden: true, ignore_files: false, include: Vec::new(),
+                 exclude: Vec::new(), max_size: None }
+    }
+
+    /// Include, or with `exclude` leave out, the files of the type `name`
+    /// in `TYPES`.
+    pub fn add_type(&mut self, name: &str, exclude: bool) -> Result<(), Error> {
+        let globs = match TYPES.iter().find(|t| t.0 == name) {
+            Some(t) => t.1,
+            None => return Err(Error::Argument(format!(
+                "unknown file type '{}'; known types are {}", name,
+                TYPES.iter().map(|t| t.0).collect::<Vec<_>>().join(", ")))),
+        };
+        let rules = globs.iter().map(|g| Rule::new(g, false));
+        if exclude {
+            self.exclude.extend(rules);
+        } else {
+            self.include.extend(rules);
+        }
+        Ok(())
+    }
+}
+
+/// The ignore files of a directory and of those above it.
+struct Ignores {
+    /// Where the paths the ignore file applies to are found.
+    dir: PathBuf,
+    /// Path of `dir` from the directory holding the ignore file; empty
+    /// unless that is above the directory walked.
+    prefix: PathBuf,
+    ignore: Ignore,
+    parent: Option<Rc<Ignores>>,
+}
+
+impl Ignores {
+    /// Whether the nearest ignore file with an opinion ignores `path`.
+    fn ignores(&self, path: &Path, is_dir: bool) -> bool {
+        let mut ignores = Some(self);
+        while let Some(i) = ignores {
+            if let Ok(relative) = path.strip_prefix(&i.dir) {
+                let relative = i.prefix.join(relative);
+                let relative = relative.to_string_lossy();
+                if let Some(ignored) = i.ignore.ignores(&relative, is_dir) {
+                    return ignored;
+                }
+            }
+            ignores = i.parent.as_deref();
+        }
+        false
+    }
+
+    /// The ignore files in the directories above `root`, up to the top of
+    /// the git repository `root` is in, nearest first.  Outside a
+    /// repository there are none.
+    fn above(root: &Path) -> Option<Rc<Ignores>> {
+        let real = fs::canonicalize(root).ok()?;
+        if real.join(".git").exists() {
+            return None;
+        }
+        let mut dirs = Vec::new();
+        for dir in real.ancestors().skip(1) {
+            dirs.push(dir);
+            if dir.join(".git").exists() {
+                break;
+            }
+        }
+        if !dirs.last().is_some_and(|dir| dir.join(".git").exists()) {
+            return None;
+        }
+        // Fold from the top down, so the nearest ends up first.
+        dirs.into_iter().rev().fold(None, |parent, dir| match Ignore::from_dir(dir) {
+            Some(ignore) => Some(Rc::new(Ignores {
+                dir: root.to_path_buf(),
+                prefix: real.strip_prefix(dir).unwrap_or(&real).to_path_buf(),
+                ignore,
+                parent,
+            })),
+            None => parent,
+        })
+    }
+}
+
+/// A path waiting to be visited.
+struct Entry {
+    path: PathBuf,
+    depth: usize,
+    /// The path given to the walk that this one was found under.
+    root: Rc<PathBuf>,
+    ignores: Option<Rc<Ignores>>,
+}
+
+/// Iterator over the files under a set of paths.
+pub struct Walk {
+    stack: Vec<Entry>,
+    follow_links: bool,
+    max_depth: Option<usize>,
+    filter: Filter,
+    visited: HashSet<PathBuf>,
+}
+
+impl Walk {
+    /// Walk `paths` in order, descending into directories.
+    ///
+    /// Links inside directories are skipped unless `follow_links` is set.
+    /// The given paths are at depth 0, and nothing deeper than `max_depth`
+    /// is returned.
+    pub fn new<P: AsRef<Path>>(paths: &[P], follow_links: bool,
+                               max_depth: Option<usize>) -> Walk {
+        Walk::filtered(paths, follow_links, max_depth, Filter::none())
+    }
+
+    /// Walk `paths` as `new` does, leaving out what `filter` says to.
+    pub fn filtered<P: AsRef<Path>>(paths: &[P], follow_links: bool,
+                                    max_depth: Option<usize>, filter: Filter)
+        -> Walk {
+        let stack = paths.iter().rev()
+            .map(|p| {
+                let path = p.as_ref().to_path_buf();
+                let ignores = if filter.ignore_files && path.is_dir() {
+                    Ignores::above(&path)
+                } else {
+                    None
+                };
+                Entry { root: Rc::new(path.clone()), path, depth: 0, ignores }
+            })
+            .collect();
+        Walk { stack, follow_links, max_depth, filter, visited: HashSet::new() }
+    }
+
+    fn error(path: PathBuf, error: io::Error) -> Option<Result<PathBuf, Error>> {
+        Some(Err(Error::Io { path, error }))
+    }
+
+    /// Check if `filter` leaves out `entry`, found while recursing.
+    fn leaves_out(&self, entry: &Entry, is_dir: bool) -> bool {
+        let name = entry.path.file_name().map(|name| name.to_string_lossy());
+        if is_dir && name.as_ref().is_some_and(|name| name == ".git") {
+            return true;
+        }
+        let hidden = name.is_some_and(|name| name.starts_with('.'));
+        if hidden && !self.filter.hidden {
+            return true;
+        }
+        if entry.ignores.as_ref().is_some_and(|i| i.ignores(&entry.path, is_dir)) {
+            return true;
+        }
+        let relative = entry.path.strip_prefix(&*entry.root)
+            .unwrap_or(&entry.path)
+            .to_string_lossy();
+        if self.filter.exclude.iter().any(|r| r.matches(&relative, is_dir)) {
+            return true;
+        }
+        !is_dir && !self.filter.include.is_empty()
+            && !self.filter.include.iter().any(|r| r.matches(&relative, false))
+    }
+
+    /// Queue the entries of `dir`, which is at `depth`.
+    fn push_dir(&mut self, dir: &Entry) -> io::Result<()> {
+        let Entry { path: ref dir, depth, ref root, ref ignores } = *dir;
+        if self.follow_links {
+            // Following links can lead back to a directory already seen.
+            let real = fs::canonicalize(dir)?;
+            if !self.visited.insert(real) {
+                return Err(io::Error::other("file system loop detected"));
+            }
+        }
+
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            entries.push(entry?.path());
+        }
+        entries.sort();
+
+        let ignores = match (self.filter.ignore_files, Ignore::from_dir(dir)) {
+            (true, Some(ignore)) => Some(Rc::new(Ignores {
+                dir: dir.to_path_buf(),
+                prefix: PathBuf::new(),
+                ignore,
+                parent: ignores.clone(),
+            })),
+            _ => ignores.clone(),
+        };
+        for path in entries.into_iter().rev() {
+            self.stack.push(Entry { path, depth: depth + 1, root: root.clone(),
+                                    ignores: ignores.clone() });
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for Walk {
+    type Item = Result<PathBuf, Error>;
+
+    fn next(&mut self) -> Option<Result<PathBuf, Error>> {
+        while let Some(entry) = self.stack.pop() {
+            let link = match fs::symlink_metadata(&entry.path) {
+                Ok(meta) => meta.file_type().is_symlink(),
+                Err(e) => return Walk::error(entry.path, e),
+            };
+            if link && entry.depth > 0 && !self.follow_links {
+                continue;
+            }
+
+            let meta = match fs::metadata(&entry.path) {
+                Ok(meta) => meta,
+                Err(e) => return Walk::error(entry.path, e),
+            };
+            if entry.depth > 0 && self.leaves_out(&entry, meta.is_dir()) {
+                continue;
+            }
+            if !meta.is_dir() {
+                if self.filter.max_size.is_some_and(|max| meta.len() > max) {
+                    continue;
+                }
+                return Some(Ok(entry.path));
+            }
+            if self.max_depth.is_some_and(|max| entry.depth >= max) {
+                continue;
+            }
+            if let Err(e) = self.push_dir(&entry) {
+                return Walk::error(entry.path, e);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+
+    /// Build a small tree under the system temporary directory.
+    fn tree(name: &str) -> PathBuf {
+        let root = env::temp_dir().join(format!("minigrep-walk-{}-{}",
+                                                name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("a/b")).unwrap();
+        fs::write(root.join("top.txt"), "top").unwrap();
+        fs::write(root.join("a/one.txt"), "one").unwrap();
+        fs::write(root.join("a/b/two.txt"), "two").unwrap();
+        root
+    }
+
+    fn names(root: &Path, walk: Walk) -> Vec<String> {
+        walk.map(|p| p.unwrap().strip_prefix(root).unwrap()
+                 .to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn walks_sorted_and_recursive() {
+        let root = tree("sorted");
+        assert_eq!(vec!["a/b/two.txt", "a/one.txt", "top.txt"],
+                   names(&root, Walk::new(&[&root], false, None)));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn max_depth() {
+        let root = tree("depth");
+        assert_eq!(vec!["a/one.txt", "top.txt"],
+                   names(&root, Walk::new(&[&root], false, Some(2))));
+        assert_eq!(vec!["top.txt"],
+                   names(&root, Walk::new(&[&root], false, Some(1))));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn missing_path_is_an_error() {
+        let root = tree("missing");
+        let mut walk = Walk::new(&[root.join("nope"), root.join("top.txt")],
+                                 false, None);
+        assert!(walk.next().unwrap().is_err());
+        assert!(walk.next().unwrap().is_ok());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn filters() {
+        let root = tree("filter");
+        fs::create_dir_all(root.join(".git")).unwrap();
+        fs::write(root.join(".git/config"), "").unwrap();
+        fs::write(root.join(".hidden.txt"), "").unwrap();
+        fs::write(root.join("main.rs"), "").unwrap();
+        fs::write(root.join(".gitignore"), "b/\n*.txt\n!one.txt\n").unwrap();
+        fs::write(root.join("a/.ignore"), "one.txt\n").unwrap();
+
+        let mut filter = Filter::none();
+        filter.hidden = false;
+        filter.ignore_files = true;
+        assert_eq!(vec!["main.rs"],
+                   names(&root, Walk::filtered(&[&root], false, None,
+                                               filter.clone())));
+        // Hidden files are searched then, but never what is in `.git`.
+        filter.hidden = true;
+        assert_eq!(vec![".gitignore", "a/.ignore", "main.rs"],
+                   names(&root, Walk::filtered(&[&root], false, None,
+                                               filter.clone())));
+        assert!(!names(&root, Walk::new(&[&root], false, None))
+                .contains(&".git/config".to_string()));
+
+        filter.hidden = false;
+        filter.ignore_files = false;
+        filter.add_type("rust", true).unwrap();
+        filter.exclude.push(Rule::new("b", false));
+        assert_eq!(vec!["a/one.txt", "top.txt"],
+                   names(&root, Walk::filtered(&[&root], false, None,
+                                               filter.clone())));
+
+        filter.include.push(Rule::new("a/*.txt", false));
+        assert_eq!(vec!["a/one.txt"],
+                   names(&root, Walk::filtered(&[&root], false, None,
+                                               filter.clone())));
+        filter.max_size = Some(0);
+        fs::write(root.join("a/one.txt"), "not empty").unwrap();
+        assert!(Walk::filtered(&[&root], false, None, filter).next().is_none());
+        assert!(Filter::none().add_type("cobol", false).is_err());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn ignore_files_above_the_root() {
+        let root = tree("above");
+        fs::write(root.join(".gitignore"), "b/\n/a/one.txt\n").unwrap();
+        let mut filter = Filter::none();
+        filter.hidden = false;
+        filter.ignore_files = true;
+        let walk = |filter: &Filter| names(&root, Walk::filtered(
+            &[root.join("a")], false, None, filter.clone()));
+        // Outside a repository, the ignore file above is not read.
+        assert_eq!(vec!["a/b/two.txt", "a/one.txt"], walk(&filter));
+
+        fs::create_dir_all(root.join(".git")).unwrap();
+        assert!(walk(&filter).is_empty());
+        fs::write(root.join("a/.ignore"), "!one.txt\n").unwrap();
+        assert_eq!(vec!["a/one.txt"], walk(&filter));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_followed_only_on_request() {
+        let root = tree("links");
+        ::std::os::unix::fs::symlink(root.join("a"), root.join("a/b/loop"))
+            .unwrap();
+        assert_eq!(3, Walk::new(&[&root], false, None).count());
+
+        let results: Vec<_> = Walk::new(&[&root], true, None).collect();
+        assert_eq!(3, results.iter().filter(|r| r.is_ok()).count());
+        assert_eq!(1, results.iter().filter(|r| r.is_err()).count());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}